@@ -9,6 +9,9 @@ pub struct AppConfig {
     pub orchestrator: Orchestrator,
     pub api: ApiConfig,
     pub routing: RoutingConfig,
+    pub storage: StorageConfig,
+    pub reconciliation: ReconciliationConfig,
+    pub resources: ResourcesConfig,
     #[serde(rename(deserialize = "loglevel"))]
     pub log_level: String,
 }
@@ -20,6 +23,8 @@ pub struct DockerConfig {
     pub network: String,
     #[serde(rename(deserialize = "sourcedirectory"))]
     pub source_directory: String, // TODO move to a path to check exists and avoid trailing slashes
+    #[serde(rename(deserialize = "knownhosts"))]
+    pub known_hosts: String, // OpenSSH known hosts file trusted for git over ssh
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -34,6 +39,48 @@ pub struct ApiConfig {
 pub struct RoutingConfig {
     pub domain: String, // TODO check domain is http acceptable domain
     pub dashboard: bool,
+    pub tls: TlsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct TlsConfig {
+    #[serde(rename(deserialize = "acmeemail"))]
+    pub acme_email: Option<String>,
+    #[serde(rename(deserialize = "acmeserver"))]
+    pub acme_server: String, // ACME directory url, a local Pebble server can be used for testing
+    #[serde(rename(deserialize = "acmecacertificate"))]
+    pub acme_ca_certificate: Option<String>, // CA certificate trusted for the ACME server, ie the Pebble one
+    pub redirect: bool, // Redirect http requests of applications served with tls to https
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub directory: String,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ReconciliationConfig {
+    pub interval: u64, // In seconds, 0 disables the background reconciliation
+    #[serde(rename(deserialize = "autoscalinginterval"))]
+    pub autoscaling_interval: u64, // In seconds between load measures of autoscaled processes on Docker, 0 disables autoscaling
+    #[serde(rename(deserialize = "releasetimeout"))]
+    pub release_timeout: u64, // In seconds a release command can run before the deployment is aborted
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ResourcesConfig {
+    #[serde(rename(deserialize = "defaultcpu"))]
+    pub default_cpu: Option<u32>, // Cpu limit in millicores of applications without one, unlimited when unset
+    #[serde(rename(deserialize = "defaultmemory"))]
+    pub default_memory: Option<u32>, // Memory limit in MiB of applications without one, unlimited when unset
+    #[serde(rename(deserialize = "maxcpu"))]
+    pub max_cpu: Option<u32>, // Maximum cpu limit and request in millicores an application can ask for
+    #[serde(rename(deserialize = "maxmemory"))]
+    pub max_memory: Option<u32>, // Maximum memory limit and request in MiB an application can ask for
 }
 
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
@@ -54,6 +101,9 @@ impl Default for AppConfig {
             orchestrator: Orchestrator::Docker(Default::default()),
             api: Default::default(),
             routing: Default::default(),
+            storage: Default::default(),
+            reconciliation: Default::default(),
+            resources: Default::default(),
             log_level: LevelFilter::Info.to_string(),
         }
     }
@@ -65,6 +115,7 @@ impl Default for DockerConfig {
             socket: "/var/run/docker.sock".to_string(),
             network: "cleverclown".to_string(),
             source_directory: "/tmp".to_string(),
+            known_hosts: format!("{}/.ssh/known_hosts", std::env::var("HOME").unwrap_or("/root".to_string())),
         }
     }
 }
@@ -84,11 +135,41 @@ impl Default for ApiConfig {
     }
 }
 
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            directory: "/var/lib/cleverclown".to_string(),
+        }
+    }
+}
+
+impl Default for ReconciliationConfig {
+    fn default() -> Self {
+        Self {
+            interval: 30,
+            autoscaling_interval: 15,
+            release_timeout: 600,
+        }
+    }
+}
+
 impl Default for RoutingConfig {
     fn default() -> Self {
         Self {
             domain: "clever.clown".to_string(), // TODO decide extension cause clown is not a usable TLD 
             dashboard: true,
+            tls: Default::default(),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            acme_email: None,
+            acme_server: "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+            acme_ca_certificate: None,
+            redirect: true,
         }
     }
 }
@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use tokio::sync::broadcast;
+
+use super::{
+    model::{Deployment, DeploymentPhase},
+    now,
+    port::{DeploymentReporter, DeploymentRepository},
+};
+
+/// Keep the stored deployment up to date with the progress of its reconciliation
+pub struct DeploymentTracker<'a> {
+    pub deployment: Mutex<Deployment>,
+    pub repository: &'a (dyn DeploymentRepository + Sync + Send),
+    pub live_log: Arc<Mutex<LiveLog>>,
+}
+
+impl DeploymentTracker<'_> {
+    pub fn id(&self) -> String {
+        self.deployment
+            .lock()
+            .expect("Deployment poisoned")
+            .id
+            .clone()
+    }
+
+    pub async fn finish(&self, result: Result<(), Error>) {
+        match result {
+            Ok(()) => self.log("Deployment done".to_string()).await,
+            Err(ref e) => self.log(format!("Deployment failed: {:#}", e)).await,
+        }
+        let deployment = {
+            let mut deployment = self.deployment.lock().expect("Deployment poisoned");
+            match result {
+                Ok(()) => deployment.phase = DeploymentPhase::Done,
+                Err(e) => {
+                    deployment.phase = DeploymentPhase::Failed;
+                    deployment.error = Some(format!("{:#}", e));
+                }
+            }
+            deployment.updated_at = now();
+            deployment.finished_at = Some(deployment.updated_at);
+            deployment.clone()
+        };
+        self.save(&deployment).await
+    }
+
+    async fn save(&self, deployment: &Deployment) {
+        if let Err(e) = self.repository.save(deployment).await {
+            warn!("Can't save deployment {} {:?}", deployment.id, e);
+        }
+    }
+}
+
+#[async_trait]
+impl DeploymentReporter for DeploymentTracker<'_> {
+    async fn phase(&self, phase: DeploymentPhase) {
+        let deployment = {
+            let mut deployment = self.deployment.lock().expect("Deployment poisoned");
+            deployment.phase = phase;
+            deployment.updated_at = now();
+            deployment.clone()
+        };
+        self.save(&deployment).await
+    }
+
+    async fn log(&self, line: String) {
+        info!("{}", line);
+        self.live_log
+            .lock()
+            .expect("Live log poisoned")
+            .push(line.clone());
+        let deployment_id = self.id();
+        if let Err(e) = self
+            .repository
+            .append_log(deployment_id.clone(), line)
+            .await
+        {
+            warn!("Can't save log of deployment {} {:?}", deployment_id, e);
+        }
+    }
+
+    async fn commit(&self, sha: String) {
+        let deployment = {
+            let mut deployment = self.deployment.lock().expect("Deployment poisoned");
+            deployment.commit = Some(sha);
+            deployment.updated_at = now();
+            deployment.clone()
+        };
+        self.save(&deployment).await
+    }
+}
+
+/// Log lines of a running deployment, replayed to subscribers joining late
+pub struct LiveLog {
+    lines: Vec<String>,
+    sender: broadcast::Sender<String>,
+}
+
+impl Default for LiveLog {
+    fn default() -> Self {
+        Self {
+            lines: vec![],
+            sender: broadcast::channel(1024).0,
+        }
+    }
+}
+
+impl LiveLog {
+    fn push(&mut self, line: String) {
+        self.lines.push(line.clone());
+        // No receiver is not an error, lines are kept for later subscribers
+        let _ = self.sender.send(line);
+    }
+
+    pub fn subscribe(&self) -> DeploymentLogs {
+        DeploymentLogs {
+            history: self.lines.clone(),
+            live: Some(self.sender.subscribe()),
+        }
+    }
+}
+
+pub struct DeploymentLogs {
+    pub history: Vec<String>,
+    /// Following lines until the end of the deployment, `None` when already finished
+    pub live: Option<broadcast::Receiver<String>>,
+}
+
+/// Reporter for reconciliations which are not tracked as a deployment
+pub struct LogReporter;
+
+#[async_trait]
+impl DeploymentReporter for LogReporter {
+    async fn phase(&self, phase: DeploymentPhase) {
+        debug!("Reconciliation phase {:?}", phase);
+    }
+
+    async fn log(&self, line: String) {
+        info!("{}", line);
+    }
+
+    async fn commit(&self, sha: String) {
+        debug!("Reconciliation commit {}", sha);
+    }
+}
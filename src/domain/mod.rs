@@ -1,113 +1,226 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::{ResourcesConfig, RoutingConfig};
 use anyhow::{anyhow, Error};
-use log::info;
-use model::{Application, Container};
-use port::ContainerExecutor;
+use deployment::{DeploymentLogs, DeploymentTracker, LiveLog, LogReporter};
+use futures::{stream::BoxStream, StreamExt};
+use itertools::Itertools;
+use log::{error, info, warn};
+use model::{
+    Addon, AddonAttachment, AddonKind, Application, ApplicationDetail, ApplicationSource,
+    Autoscaling, Certificate, Container, ContainerState, Deployment, DeploymentPhase, Environment,
+    ExecOptions, GitCredential, Health, HealthCheck, LogLine, LogOptions, Process, ProcessLoad,
+    RegistryCredential, Release, Resources, RunOutput, Secret, SourceCredentials, Tls,
+    VolumeDetail,
+};
+use port::{
+    AddonRepository, ApplicationRepository, ContainerExecutor, DeploymentReporter,
+    DeploymentRepository, ExecSession, GitCredentialRepository, RegistryCredentialRepository,
+    ReleaseRepository, SecretRepository,
+};
+use rand::{distributions::Alphanumeric, Rng};
 use split_iter::Splittable;
+use tokio::{
+    sync::Mutex,
+    time::{interval, sleep, Instant, MissedTickBehavior},
+};
 
+pub mod deployment;
 pub mod model;
 pub mod port;
 
 pub struct ReconciliationService {
-    // pub application_repository: Box<dyn ApplicationRepository>,
+    pub application_repository: Box<dyn ApplicationRepository + 'static + Sync + Send>,
+    pub deployment_repository: Box<dyn DeploymentRepository + 'static + Sync + Send>,
+    pub secret_repository: Box<dyn SecretRepository + 'static + Sync + Send>,
+    pub release_repository: Box<dyn ReleaseRepository + 'static + Sync + Send>,
+    pub git_credential_repository: Box<dyn GitCredentialRepository + 'static + Sync + Send>,
+    pub registry_credential_repository:
+        Box<dyn RegistryCredentialRepository + 'static + Sync + Send>,
+    pub addon_repository: Box<dyn AddonRepository + 'static + Sync + Send>,
     pub container_executor: Box<dyn ContainerExecutor + 'static + Sync + Send>,
+    pub routing_config: RoutingConfig,
+    pub resources_config: ResourcesConfig,
+    /// Bound of the release command, a deployment holds the application lock while it runs
+    pub release_timeout: Duration,
+    pub application_locks: std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    pub live_logs: std::sync::Mutex<HashMap<String, Arc<std::sync::Mutex<LiveLog>>>>,
+    /// Applications being deployed, their routes are claimed until they are stored
+    pub route_claims: std::sync::Mutex<HashMap<String, Application>>,
+    /// Replicas chosen by the autoscaler for each application and process, kept in memory
+    pub autoscaled_replicas: std::sync::Mutex<HashMap<(String, String), u8>>,
+}
+
+impl ReconciliationService {
+    fn application_lock(&self, application_name: &str) -> Arc<Mutex<()>> {
+        self.application_locks
+            .lock()
+            .expect("Application locks poisoned")
+            .entry(application_name.to_string())
+            .or_default()
+            .clone()
+    }
+
+    fn live_log(&self, deployment_id: &str) -> Option<Arc<std::sync::Mutex<LiveLog>>> {
+        self.live_logs
+            .lock()
+            .expect("Live logs poisoned")
+            .get(deployment_id)
+            .cloned()
+    }
+
+    /// Desired replicas of the process, the ones chosen by the autoscaler within its bounds.
+    /// Until it chose any, ie after a restart, the `running` instances are kept
+    fn replicas(&self, application_name: &str, process: &Process, running: usize) -> u8 {
+        let Some(ref autoscaling) = process.autoscaling else {
+            return process.replicas();
+        };
+        self.autoscaled_replicas
+            .lock()
+            .expect("Autoscaled replicas poisoned")
+            .get(&(application_name.to_string(), process.name.clone()))
+            .copied()
+            .unwrap_or(u8::try_from(running).unwrap_or(u8::MAX))
+            .max(autoscaling.min)
+            .min(autoscaling.max)
+    }
 }
 
 pub enum Event {
-    Deploy(Application),
-    Destroy(String),
+    Deploy(Box<Application>),
+    /// Stop and remove the application, its volumes are only removed with `purge_volumes`
+    Destroy {
+        application: String,
+        purge_volumes: bool,
+    },
+    /// Redeploy the image of a previous release, the one before the current when unspecified
+    Rollback {
+        application: String,
+        release: Option<u32>,
+    },
 }
 
-pub async fn reconcile(event: Event, service: &ReconciliationService) -> Result<(), Error> {
+pub async fn reconcile(
+    event: Event,
+    service: &ReconciliationService,
+    reporter: &dyn DeploymentReporter,
+) -> Result<(), Error> {
     match event {
-        Event::Deploy(application) => {
+        Event::Deploy(mut application) => {
+            let lock = service.application_lock(&application.name);
+            let _guard = lock.lock().await;
+            keep_attachments(&mut application, service).await?;
+            // Checked again as applications deployed meanwhile aren't stored until rolled out
+            let _claim = RouteClaim::new(&application, service).await?;
+            let environment = environment(&application, service).await?;
+            let credentials = source_credentials(&application, service).await?;
             let image_id = service
                 .container_executor
-                .register_image(&application)
+                .register_image(&application, &credentials, reporter)
                 .await?;
             info!("Application image detected : {}", image_id);
-            let app_containers = service
-                .container_executor
-                .register_application(&application, image_id.clone())
+            release_phase(
+                &application,
+                image_id.clone(),
+                &environment,
+                service,
+                reporter,
+            )
+            .await?;
+            reporter.phase(DeploymentPhase::RollingOut).await;
+            converge(
+                &application,
+                image_id.clone(),
+                &environment,
+                service,
+                reporter,
+            )
+            .await?;
+            // Saved once rolled out, an aborted rollout keeps the previous config and image as desired state
+            service.application_repository.save(&application).await?;
+            service
+                .application_repository
+                .save_image(application.name.clone(), image_id.clone())
                 .await?;
-            let (outdated_containers, valid_containers) = app_containers
-                .into_iter()
-                .split(|container| container.image_id.eq(&image_id));
-            let outdated_containers: Vec<Container> = outdated_containers.collect();
-            if !outdated_containers.is_empty() {
-                info!(
-                    "Detected {} outdated container runnning. Will be stopped as rolling update",
-                    outdated_containers.len()
-                );
-            }
-            // Could be reintroduced for a down then start rolling strategy
-            // for outdated in outdated_containers {
-            //     info!("Detected outdated container running {}. Stopping container...", outdated.id);
-            //     service.container_executor.stop(&outdated).await?;
-            // }
-            let mut app_containers: Vec<Container> = valid_containers.collect();
-            let target_replicas = usize::from(
-                application
-                    .configuration
-                    .as_ref()
-                    .and_then(|configuration| configuration.replicas)
-                    .unwrap_or(1),
-            );
-            if target_replicas > app_containers.len() {
-                info!(
-                    "{} running instances. Starting {} instances",
-                    app_containers.len(),
-                    target_replicas - app_containers.len()
-                );
-                let mut outdated_containers = outdated_containers.into_iter();
-                for _ in app_containers.len()..target_replicas {
-                    let container = service
-                        .container_executor
-                        .start_instance(&application, image_id.clone())
-                        .await?;
-                    info!("Instance {} started", container.id);
-                    if let Some(outdated) = outdated_containers.next() {
-                        service
-                            .container_executor
-                            .stop_instance(application.name.clone(), &outdated)
-                            .await?;
-                        info!("Outdated instance {} stopped", outdated.id);
-                    }
-                }
-                for outdated in outdated_containers {
-                    service
-                        .container_executor
-                        .stop_instance(application.name.clone(), &outdated)
-                        .await?;
-                    info!("Outdated instance {} stopped", outdated.id);
-                }
-            } else if target_replicas == app_containers.len() {
-                info!("Application is up-to-date")
-            } else {
-                info!(
-                    "{} running instances. Downscaling to {} instances",
-                    app_containers.len(),
-                    target_replicas
-                );
-                app_containers.sort_by(|a: &model::Container, b| a.started_at.cmp(&b.started_at));
-                for container in app_containers
-                    .iter()
-                    .take(app_containers.len() - target_replicas)
-                {
-                    service
-                        .container_executor
-                        .stop_instance(application.name.clone(), container)
-                        .await?;
-                    info!("Instance {} deleted", container.id);
-                }
+            record_release(*application, image_id, service, reporter).await
+        }
+        Event::Rollback {
+            application: application_name,
+            release,
+        } => {
+            let lock = service.application_lock(&application_name);
+            let _guard = lock.lock().await;
+            let releases = service
+                .release_repository
+                .list(application_name.clone())
+                .await?;
+            let mut target = match release {
+                Some(version) => releases.iter().find(|release| release.version == version),
+                None => releases.iter().rev().nth(1),
             }
-            Ok(())
+            .ok_or(anyhow!(
+                "No release {} to rollback to for application {}",
+                release
+                    .map(|version| version.to_string())
+                    .unwrap_or_default(),
+                application_name
+            ))?
+            .clone();
+            reporter
+                .log(format!(
+                    "Rollback application {} to release {} with image {}",
+                    application_name, target.version, target.image_id
+                ))
+                .await;
+            current_attachments(&mut target.application, service).await?;
+            // Hosts of the release may have been claimed by another application since
+            let _claim = RouteClaim::new(&target.application, service).await?;
+            let environment = environment(&target.application, service).await?;
+            reporter.phase(DeploymentPhase::RollingOut).await;
+            converge(
+                &target.application,
+                target.image_id.clone(),
+                &environment,
+                service,
+                reporter,
+            )
+            .await?;
+            service
+                .application_repository
+                .save(&target.application)
+                .await?;
+            service
+                .application_repository
+                .save_image(application_name, target.image_id.clone())
+                .await?;
+            record_release(target.application, target.image_id, service, reporter).await
         }
-        Event::Destroy(application_name) => {
+        Event::Destroy {
+            application: application_name,
+            purge_volumes,
+        } => {
+            let lock = service.application_lock(&application_name);
+            let _guard = lock.lock().await;
             let containers = service
                 .container_executor
                 .running(application_name.clone())
                 .await?;
-            if containers.len() < 1 {
+            let stored = service
+                .application_repository
+                .get(application_name.clone())
+                .await?;
+            // Volumes kept by a previous destroy can still be purged
+            let kept_volumes = purge_volumes
+                && !service
+                    .container_executor
+                    .volumes(application_name.clone())
+                    .await?
+                    .is_empty();
+            if containers.is_empty() && stored.is_none() && !kept_volumes {
                 return Err(anyhow!("Application {} is not running", application_name));
             }
             futures::future::join_all(containers.iter().map(|container| {
@@ -120,17 +233,1505 @@ pub async fn reconcile(event: Event, service: &ReconciliationService) -> Result<
             .collect::<Result<(), Error>>()?;
             service
                 .container_executor
-                .delete_application(application_name.clone())
+                .delete_application(application_name.clone(), purge_volumes)
+                .await?;
+            service
+                .release_repository
+                .delete(application_name.clone())
+                .await?;
+            service
+                .autoscaled_replicas
+                .lock()
+                .expect("Autoscaled replicas poisoned")
+                .retain(|(application, _), _| *application != application_name);
+            service
+                .application_repository
+                .delete(application_name)
                 .await
         }
     }
 }
 
+/// Run the release command of the application with the new image, before any instance is touched
+async fn release_phase(
+    application: &Application,
+    image_id: String,
+    environment: &Environment,
+    service: &ReconciliationService,
+    reporter: &dyn DeploymentReporter,
+) -> Result<(), Error> {
+    let Some(command) = application
+        .configuration
+        .as_ref()
+        .and_then(|configuration| configuration.release.clone())
+    else {
+        return Ok(());
+    };
+    reporter.phase(DeploymentPhase::Releasing).await;
+    // Mounted by the release instance as well
+    service
+        .container_executor
+        .register_volumes(application)
+        .await?;
+    reporter
+        .log(format!("Run release command {}", command.join(" ")))
+        .await;
+    let mut output = service
+        .container_executor
+        .run(
+            application,
+            image_id,
+            environment,
+            command,
+            Some(service.release_timeout),
+        )
+        .await
+        .map_err(|e| e.context("Release command failed to start"))?;
+    while let Some(output) = output.next().await {
+        match output? {
+            RunOutput::Line(line) => reporter.log(format!("Release => {}", line)).await,
+            RunOutput::Exited(0) => {
+                reporter.log("Release command succeeded".to_string()).await;
+                return Ok(());
+            }
+            RunOutput::Exited(code) => {
+                return Err(anyhow!("Release command failed with exit code {}", code))
+            }
+            RunOutput::TimedOut => {
+                return Err(anyhow!(
+                    "Release command timed out after {}s",
+                    service.release_timeout.as_secs()
+                ))
+            }
+        }
+    }
+    Err(anyhow!("Release command ended without exit code"))
+}
+
+/// Drive the running instances of each process of an application to its desired replicas and image
+async fn converge(
+    application: &Application,
+    image_id: String,
+    environment: &Environment,
+    service: &ReconciliationService,
+    reporter: &dyn DeploymentReporter,
+) -> Result<(), Error> {
+    service
+        .container_executor
+        .register_volumes(application)
+        .await?;
+    let app_containers = service
+        .container_executor
+        .register_application(application, image_id.clone(), environment)
+        .await?;
+    let (exited_containers, app_containers) = app_containers
+        .into_iter()
+        .split(|container| container.state != ContainerState::Exited);
+    let exited_containers: Vec<Container> = exited_containers.collect();
+    let app_containers: Vec<Container> = app_containers.collect();
+    for exited in exited_containers {
+        service
+            .container_executor
+            .stop_instance(application.name.clone(), &exited)
+            .await?;
+        reporter
+            .log(format!("Exited instance {} removed", exited.id))
+            .await;
+    }
+    let processes = application.processes();
+    let mut process_containers = app_containers
+        .into_iter()
+        .into_group_map_by(|container| container.process.clone());
+    for process in processes.iter() {
+        converge_process(
+            application,
+            process,
+            process_containers.remove(&process.name).unwrap_or_default(),
+            image_id.clone(),
+            environment,
+            service,
+            reporter,
+        )
+        .await?;
+    }
+    // Left instances run a process removed from the application
+    for container in process_containers.into_values().flatten() {
+        service
+            .container_executor
+            .stop_instance(application.name.clone(), &container)
+            .await?;
+        reporter
+            .log(format!(
+                "Instance {} of removed process {} stopped",
+                container.id, container.process
+            ))
+            .await;
+    }
+    Ok(())
+}
+
+/// Rolling update and scaling of the instances of a process, the new instances replace outdated ones one by one
+async fn converge_process(
+    application: &Application,
+    process: &Process,
+    app_containers: Vec<Container>,
+    image_id: String,
+    environment: &Environment,
+    service: &ReconciliationService,
+    reporter: &dyn DeploymentReporter,
+) -> Result<(), Error> {
+    let (outdated_containers, valid_containers) = app_containers.into_iter().split(|container| {
+        // Spec changes, ie an add-on attachment or new resources, roll the instances too
+        container.image_id.eq(&image_id)
+            && container.checksum.as_ref().is_none_or(|checksum| {
+                checksum.eq(&application.instance_checksum(process, environment))
+            })
+    });
+    let outdated_containers: Vec<Container> = outdated_containers.collect();
+    let mut app_containers: Vec<Container> = valid_containers.collect();
+    if !outdated_containers.is_empty() {
+        reporter
+            .log(format!(
+                "Detected {} outdated container runnning for process {}. Will be stopped as rolling update",
+                outdated_containers.len(),
+                process.name
+            ))
+            .await;
+    }
+    // Could be reintroduced for a down then start rolling strategy
+    // for outdated in outdated_containers {
+    //     info!("Detected outdated container running {}. Stopping container...", outdated.id);
+    //     service.container_executor.stop(&outdated).await?;
+    // }
+    // Runtime scaled instances are only rolled, the first deployment starts the minimum replicas
+    let target_replicas = match process.autoscaling {
+        Some(_) if service.container_executor.scales_processes() => {
+            match outdated_containers.len() + app_containers.len() {
+                0 => usize::from(process.replicas()),
+                running => running,
+            }
+        }
+        _ => usize::from(service.replicas(
+            &application.name,
+            process,
+            outdated_containers.len() + app_containers.len(),
+        )),
+    };
+    if target_replicas > app_containers.len() {
+        reporter
+            .log(format!(
+                "{} running instances of process {}. Starting {} instances",
+                app_containers.len(),
+                process.name,
+                target_replicas - app_containers.len()
+            ))
+            .await;
+        let mut outdated_containers = outdated_containers.into_iter();
+        for _ in app_containers.len()..target_replicas {
+            let container = service
+                .container_executor
+                .start_instance(application, process, image_id.clone(), environment)
+                .await?;
+            reporter
+                .log(format!("Instance {} started", container.id))
+                .await;
+            if let Some(healthcheck) = application.healthcheck(process) {
+                if let Err(e) =
+                    wait_healthy(application, &container, healthcheck, service, reporter).await
+                {
+                    service
+                        .container_executor
+                        .stop_instance(application.name.clone(), &container)
+                        .await?;
+                    reporter
+                        .log(format!("Unhealthy instance {} stopped", container.id))
+                        .await;
+                    return Err(e.context("Rolling update aborted"));
+                }
+            }
+            if let Some(outdated) = outdated_containers.next() {
+                service
+                    .container_executor
+                    .stop_instance(application.name.clone(), &outdated)
+                    .await?;
+                reporter
+                    .log(format!("Outdated instance {} stopped", outdated.id))
+                    .await;
+            }
+        }
+        for outdated in outdated_containers {
+            service
+                .container_executor
+                .stop_instance(application.name.clone(), &outdated)
+                .await?;
+            reporter
+                .log(format!("Outdated instance {} stopped", outdated.id))
+                .await;
+        }
+    } else if target_replicas == app_containers.len() {
+        reporter
+            .log(format!("Process {} is up-to-date", process.name))
+            .await;
+    } else {
+        reporter
+            .log(format!(
+                "{} running instances of process {}. Downscaling to {} instances",
+                app_containers.len(),
+                process.name,
+                target_replicas
+            ))
+            .await;
+        app_containers.sort_by_key(|container| container.started_at);
+        for container in app_containers
+            .iter()
+            .take(app_containers.len() - target_replicas)
+        {
+            service
+                .container_executor
+                .stop_instance(application.name.clone(), container)
+                .await?;
+            reporter
+                .log(format!("Instance {} deleted", container.id))
+                .await;
+        }
+    }
+    Ok(())
+}
+
+/// Wait for the instance to pass its health check, failing once unhealthy or out of retries
+async fn wait_healthy(
+    application: &Application,
+    container: &Container,
+    healthcheck: &HealthCheck,
+    service: &ReconciliationService,
+    reporter: &dyn DeploymentReporter,
+) -> Result<(), Error> {
+    reporter
+        .log(format!("Waiting instance {} to be healthy", container.id))
+        .await;
+    let deadline = Instant::now()
+        + Duration::from_secs(
+            (healthcheck.interval() + healthcheck.timeout())
+                * (u64::from(healthcheck.retries()) + 1),
+        );
+    loop {
+        match service
+            .container_executor
+            .instance_health(application.name.clone(), container)
+            .await?
+        {
+            Health::Healthy => {
+                reporter
+                    .log(format!("Instance {} is healthy", container.id))
+                    .await;
+                return Ok(());
+            }
+            Health::Unhealthy => {
+                return Err(anyhow!("Instance {} is unhealthy", container.id));
+            }
+            Health::Starting if Instant::now() >= deadline => {
+                return Err(anyhow!(
+                    "Instance {} didn't become healthy in time",
+                    container.id
+                ));
+            }
+            Health::Starting => sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+async fn record_release(
+    application: Application,
+    image_id: String,
+    service: &ReconciliationService,
+    reporter: &dyn DeploymentReporter,
+) -> Result<(), Error> {
+    let version = service
+        .release_repository
+        .list(application.name.clone())
+        .await?
+        .last()
+        .map(|release| release.version + 1)
+        .unwrap_or(1);
+    service
+        .release_repository
+        .save(&Release {
+            version,
+            application,
+            image_id,
+            created_at: now(),
+        })
+        .await?;
+    reporter.log(format!("Release {} recorded", version)).await;
+    Ok(())
+}
+
+/// Resolve the environment variables of the application instances and its certificate with the referenced secrets,
+/// along with its resources
+async fn environment(
+    application: &Application,
+    service: &ReconciliationService,
+) -> Result<Environment, Error> {
+    let resources = resources(application, &service.resources_config)?;
+    let Some(configuration) = application.configuration.as_ref() else {
+        return Ok(Environment {
+            resources,
+            ..Default::default()
+        });
+    };
+    let mut secrets = HashMap::new();
+    for (variable, secret_name) in configuration.secrets.iter().flatten() {
+        secrets.insert(
+            variable.clone(),
+            secret_value(secret_name, variable, service).await?,
+        );
+    }
+    for attachment in configuration.addons.iter().flatten() {
+        let addon = service
+            .addon_repository
+            .get(attachment.addon.clone())
+            .await?
+            .ok_or(anyhow!(
+                "Add-on {} attached to {} doesn't exist",
+                attachment.addon,
+                application.name
+            ))?;
+        let variable = attachment
+            .variable
+            .clone()
+            .unwrap_or(addon.kind.default_variable().to_string());
+        if configuration
+            .env
+            .as_ref()
+            .is_some_and(|env| env.contains_key(&variable))
+            || secrets.contains_key(&variable)
+        {
+            return Err(anyhow!(
+                "Variable {} of add-on {} is already set",
+                variable,
+                addon.name
+            ));
+        }
+        secrets.insert(variable, addon.url());
+    }
+    let certificate = match configuration.tls {
+        Some(Tls::Certificate {
+            ref certificate,
+            ref key,
+        }) => Some(Certificate {
+            certificate: secret_value(certificate, "tls certificate", service).await?,
+            key: secret_value(key, "tls key", service).await?,
+        }),
+        _ => None,
+    };
+    Ok(Environment {
+        variables: configuration.env.clone().unwrap_or_default(),
+        secrets,
+        certificate,
+        resources,
+    })
+}
+
+/// Resources of the application instances, limits default to the platform ones and none can exceed its maxima
+fn resources(application: &Application, config: &ResourcesConfig) -> Result<Resources, Error> {
+    let configured = application
+        .configuration
+        .as_ref()
+        .and_then(|configuration| configuration.resources.clone())
+        .unwrap_or_default();
+    let resources = Resources {
+        cpu_limit: configured.cpu_limit.or(config.default_cpu),
+        memory_limit: configured.memory_limit.or(config.default_memory),
+        ..configured
+    };
+    let checks = [
+        (
+            "cpu",
+            resources.cpu_limit,
+            resources.cpu_request,
+            config.max_cpu,
+            "m",
+        ),
+        (
+            "memory",
+            resources.memory_limit,
+            resources.memory_request,
+            config.max_memory,
+            "Mi",
+        ),
+    ];
+    for (resource, limit, request, max, unit) in checks {
+        if let Some((amount, max)) = limit
+            .max(request)
+            .zip(max)
+            .filter(|(amount, max)| amount > max)
+        {
+            return Err(anyhow!(
+                "The {} of {}{} exceeds the maximum of {}{}",
+                resource,
+                amount,
+                unit,
+                max,
+                unit
+            ));
+        }
+        if let Some((request, limit)) = request
+            .zip(limit)
+            .filter(|(request, limit)| request > limit)
+        {
+            return Err(anyhow!(
+                "The {} request of {}{} exceeds its limit of {}{}",
+                resource,
+                request,
+                unit,
+                limit,
+                unit
+            ));
+        }
+    }
+    Ok(resources)
+}
+
+async fn secret_value(
+    secret_name: &str,
+    referenced_by: &str,
+    service: &ReconciliationService,
+) -> Result<String, Error> {
+    service
+        .secret_repository
+        .get(secret_name.to_string())
+        .await?
+        .map(|secret| secret.value)
+        .ok_or(anyhow!(
+            "Secret {} referenced by {} doesn't exist",
+            secret_name,
+            referenced_by
+        ))
+}
+
+/// Credential referenced by the git source of the application and the one stored for its image registry
+async fn source_credentials(
+    application: &Application,
+    service: &ReconciliationService,
+) -> Result<SourceCredentials, Error> {
+    let git = match application.source {
+        ApplicationSource::Git {
+            credential: Some(ref credential_name),
+            ..
+        } => Some(
+            service
+                .git_credential_repository
+                .get(credential_name.clone())
+                .await?
+                .ok_or(anyhow!(
+                    "Git credential {} referenced by application {} doesn't exist",
+                    credential_name,
+                    application.name
+                ))?,
+        ),
+        _ => None,
+    };
+    // Images of registries without a stored credential are pulled anonymously
+    let registry = match application.source.registry() {
+        Some(registry) => service.registry_credential_repository.get(registry).await?,
+        None => None,
+    };
+    Ok(SourceCredentials { git, registry })
+}
+
+/// Platform resources served at the root of the api, where they would shadow an application of the same name
+const RESERVED_NAMES: [&str; 5] = [
+    "secrets",
+    "git-credentials",
+    "registry-credentials",
+    "addons",
+    "deployments",
+];
+
+/// Register a deployment of the application and run it in background
+pub async fn deploy(
+    service: Arc<ReconciliationService>,
+    application: Application,
+) -> Result<Deployment, Error> {
+    if !resource_name(&application.name) {
+        return Err(anyhow!(
+            "Application name {} must be lowercase alphanumerics and single dashes",
+            application.name
+        ));
+    }
+    if RESERVED_NAMES.contains(&application.name.as_str()) {
+        return Err(anyhow!(
+            "Application name {} is reserved by the platform",
+            application.name
+        ));
+    }
+    check_processes(&application)?;
+    check_volumes(&application)?;
+    let resources = resources(&application, &service.resources_config)?;
+    check_autoscaling(&application, &resources)?;
+    check_routes(&application, &service).await?;
+    let application_name = application.name.clone();
+    track(
+        service,
+        application_name,
+        Event::Deploy(Box::new(application)),
+    )
+    .await
+}
+
+/// Process names end up in runtime resource names and labels
+fn check_processes(application: &Application) -> Result<(), Error> {
+    let processes = application.processes();
+    if let Some(process) = processes
+        .iter()
+        .find(|process| !resource_name(&process.name))
+    {
+        return Err(anyhow!(
+            "Process name {} must be lowercase alphanumerics and single dashes",
+            process.name
+        ));
+    }
+    if let Some(name) = processes
+        .iter()
+        .map(|process| &process.name)
+        .duplicates()
+        .next()
+    {
+        return Err(anyhow!("Process {} is declared more than once", name));
+    }
+    Ok(())
+}
+
+/// Autoscaling bounds and targets, the cpu target being relative to the cpu limit
+fn check_autoscaling(application: &Application, resources: &Resources) -> Result<(), Error> {
+    for process in application.processes() {
+        let Some(ref autoscaling) = process.autoscaling else {
+            continue;
+        };
+        if autoscaling.min == 0 || autoscaling.min > autoscaling.max {
+            return Err(anyhow!(
+                "Autoscaling of process {} needs 1 <= min <= max",
+                process.name
+            ));
+        }
+        if autoscaling.target_cpu.is_none() && autoscaling.target_requests.is_none() {
+            return Err(anyhow!(
+                "Autoscaling of process {} needs a cpu or requests target",
+                process.name
+            ));
+        }
+        if autoscaling.target_cpu == Some(0) || autoscaling.target_requests == Some(0) {
+            return Err(anyhow!(
+                "Autoscaling targets of process {} must be positive",
+                process.name
+            ));
+        }
+        if autoscaling.target_cpu.is_some() && resources.cpu_limit.is_none() {
+            return Err(anyhow!(
+                "Cpu target of process {} needs a cpu limit",
+                process.name
+            ));
+        }
+        if autoscaling.target_requests.is_some() && !process.routed() {
+            return Err(anyhow!(
+                "Requests target of process {} is only supported for the {} process",
+                process.name,
+                model::WEB_PROCESS
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Volume names end up in runtime resource names and labels
+fn check_volumes(application: &Application) -> Result<(), Error> {
+    let volumes = application.volumes();
+    if let Some(volume) = volumes.iter().find(|volume| !resource_name(&volume.name)) {
+        return Err(anyhow!(
+            "Volume name {} must be lowercase alphanumerics and single dashes",
+            volume.name
+        ));
+    }
+    if let Some(volume) = volumes
+        .iter()
+        .find(|volume| !volume.mount_path.starts_with('/'))
+    {
+        return Err(anyhow!(
+            "Mount path {} of volume {} must be absolute",
+            volume.mount_path,
+            volume.name
+        ));
+    }
+    if let Some(name) = volumes
+        .iter()
+        .map(|volume| &volume.name)
+        .duplicates()
+        .next()
+    {
+        return Err(anyhow!("Volume {} is declared more than once", name));
+    }
+    Ok(())
+}
+
+/// Runtimes join names with a double dash, which keeps them unambiguous
+fn resource_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && !name.contains("--")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Reject invalid path prefixes and routes overlapping the ones of another application.
+/// Prefixes of a shared host overlap when one starts with the other, as Traefik matches them
+async fn check_routes(
+    application: &Application,
+    service: &ReconciliationService,
+) -> Result<(), Error> {
+    let domain = &service.routing_config.domain;
+    let hosts = application.hosts(domain);
+    let paths = application.paths();
+    // Hosts are interpolated in the Traefik rules
+    if let Some(host) = hosts.iter().find(|host| !dns_name(host)) {
+        return Err(anyhow!(
+            "Host {} must be a valid DNS name without wildcard",
+            host
+        ));
+    }
+    if let Some(path) = paths.iter().find(|path| {
+        !path.starts_with('/') || path.contains(|c: char| c.is_whitespace() || c == '`' || c == ',')
+    }) {
+        return Err(anyhow!(
+            "Path {} must start with / and can't contain spaces, commas or backquotes",
+            path
+        ));
+    }
+    for other in service.application_repository.list().await? {
+        check_overlap(application, &other, domain)?;
+    }
+    Ok(())
+}
+
+fn check_overlap(
+    application: &Application,
+    other: &Application,
+    domain: &str,
+) -> Result<(), Error> {
+    if other.name == application.name {
+        return Ok(());
+    }
+    let paths = application.paths();
+    let other_paths = other.paths();
+    for host in other
+        .hosts(domain)
+        .iter()
+        .filter(|host| application.hosts(domain).contains(host))
+    {
+        if let Some((path, other_path)) =
+            paths
+                .iter()
+                .cartesian_product(other_paths.iter())
+                .find(|(path, other_path)| {
+                    path.starts_with(other_path.as_str()) || other_path.starts_with(path.as_str())
+                })
+        {
+            return Err(anyhow!(
+                "Route {}{} overlaps route {}{} of application {}",
+                host,
+                path,
+                host,
+                other_path,
+                other.name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Routes of an application being deployed, held until the deployment ends so that
+/// concurrent deployments can't claim them before it is stored
+struct RouteClaim<'a> {
+    application: String,
+    service: &'a ReconciliationService,
+}
+
+impl<'a> RouteClaim<'a> {
+    async fn new(
+        application: &Application,
+        service: &'a ReconciliationService,
+    ) -> Result<RouteClaim<'a>, Error> {
+        // Claimed before the stored applications are checked, an application is stored before its claim is released
+        {
+            let mut claims = service.route_claims.lock().expect("Route claims poisoned");
+            for other in claims.values() {
+                check_overlap(application, other, &service.routing_config.domain)?;
+            }
+            claims.insert(application.name.clone(), application.clone());
+        }
+        let claim = RouteClaim {
+            application: application.name.clone(),
+            service,
+        };
+        check_routes(application, service).await?;
+        Ok(claim)
+    }
+}
+
+impl Drop for RouteClaim<'_> {
+    fn drop(&mut self) {
+        self.service
+            .route_claims
+            .lock()
+            .expect("Route claims poisoned")
+            .remove(&self.application);
+    }
+}
+
+/// Valid DNS name, Traefik `Host` rules don't match wildcards
+fn dns_name(host: &str) -> bool {
+    host.len() <= 253
+        && host.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Register a rollback of the application and run it in background
+pub async fn rollback(
+    service: Arc<ReconciliationService>,
+    application_name: String,
+    release: Option<u32>,
+) -> Result<Deployment, Error> {
+    if service
+        .release_repository
+        .list(application_name.clone())
+        .await?
+        .is_empty()
+    {
+        return Err(anyhow!(
+            "Application {} has no release to rollback to",
+            application_name
+        ));
+    }
+    let event = Event::Rollback {
+        application: application_name.clone(),
+        release,
+    };
+    track(service, application_name, event).await
+}
+
+pub async fn list_releases(
+    service: &ReconciliationService,
+    application_name: String,
+) -> Result<Vec<Release>, Error> {
+    service.release_repository.list(application_name).await
+}
+
+/// Run the event in background, tracked as a deployment
+async fn track(
+    service: Arc<ReconciliationService>,
+    application_name: String,
+    event: Event,
+) -> Result<Deployment, Error> {
+    let created_at = now();
+    let deployment = Deployment {
+        id: uuid::Uuid::new_v4().to_string(),
+        application: application_name,
+        phase: DeploymentPhase::Pending,
+        created_at,
+        updated_at: created_at,
+        finished_at: None,
+        error: None,
+        commit: None,
+    };
+    service.deployment_repository.save(&deployment).await?;
+    let live_log = Arc::new(std::sync::Mutex::new(LiveLog::default()));
+    service
+        .live_logs
+        .lock()
+        .expect("Live logs poisoned")
+        .insert(deployment.id.clone(), live_log.clone());
+    let tracker_deployment = deployment.clone();
+    tokio::spawn(async move {
+        let tracker = DeploymentTracker {
+            deployment: std::sync::Mutex::new(tracker_deployment),
+            repository: service.deployment_repository.as_ref(),
+            live_log,
+        };
+        let result = reconcile(event, &service, &tracker).await;
+        if let Err(ref e) = result {
+            error!("Error during deployment {:?}", e);
+        }
+        tracker.finish(result).await;
+        service
+            .live_logs
+            .lock()
+            .expect("Live logs poisoned")
+            .remove(&tracker.id());
+    });
+    Ok(deployment)
+}
+
+pub async fn get_deployment(
+    service: &ReconciliationService,
+    deployment_id: String,
+) -> Result<Option<Deployment>, Error> {
+    service.deployment_repository.get(deployment_id).await
+}
+
+/// Log of a deployment, with a live subscription while it is running
+pub async fn deployment_logs(
+    service: &ReconciliationService,
+    deployment_id: String,
+) -> Result<Option<DeploymentLogs>, Error> {
+    if let Some(live_log) = service.live_log(&deployment_id) {
+        return Ok(Some(
+            live_log.lock().expect("Live log poisoned").subscribe(),
+        ));
+    }
+    if service
+        .deployment_repository
+        .get(deployment_id.clone())
+        .await?
+        .is_none()
+    {
+        return Ok(None);
+    }
+    Ok(Some(DeploymentLogs {
+        history: service.deployment_repository.logs(deployment_id).await?,
+        live: None,
+    }))
+}
+
+/// Periodically drive every stored application back to its desired state
+pub async fn reconciliation_loop(service: Arc<ReconciliationService>, period: Duration) {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = reconcile_all(&service).await {
+            error!("Error during background reconciliation {:?}", e);
+        }
+    }
+}
+
+async fn reconcile_all(service: &ReconciliationService) -> Result<(), Error> {
+    // Applications are converged even when the routing can't be restored
+    if let Err(e) = service.container_executor.ensure_routing().await {
+        error!("Error while ensuring routing {:?}", e);
+    }
+    for application in service.application_repository.list().await? {
+        let lock = service.application_lock(&application.name);
+        // An application currently deployed or destroyed is left to the running event
+        let Ok(_guard) = lock.try_lock() else {
+            continue;
+        };
+        let Some(image_id) = service
+            .application_repository
+            .image(application.name.clone())
+            .await?
+        else {
+            continue;
+        };
+        let result = match environment(&application, service).await {
+            Ok(environment) => {
+                converge(&application, image_id, &environment, service, &LogReporter).await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(
+                "Background reconciliation of application {} failed {:?}",
+                application.name, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Periodically scale the autoscaled processes following the load measured by the runtime
+pub async fn autoscaling_loop(service: Arc<ReconciliationService>, period: Duration) {
+    let mut ticker = interval(period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = autoscale_all(&service).await {
+            error!("Error during autoscaling {:?}", e);
+        }
+    }
+}
+
+async fn autoscale_all(service: &ReconciliationService) -> Result<(), Error> {
+    for application in service.application_repository.list().await? {
+        if application
+            .processes()
+            .iter()
+            .all(|process| process.autoscaling.is_none())
+        {
+            continue;
+        }
+        let lock = service.application_lock(&application.name);
+        // An application currently deployed or destroyed is left to the running event
+        let Ok(_guard) = lock.try_lock() else {
+            continue;
+        };
+        let Some(image_id) = service
+            .application_repository
+            .image(application.name.clone())
+            .await?
+        else {
+            continue;
+        };
+        if let Err(e) = autoscale(&application, image_id, service).await {
+            warn!(
+                "Autoscaling of application {} failed {:?}",
+                application.name, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Choose the replicas of each autoscaled process from its load, then scale through the usual convergence
+async fn autoscale(
+    application: &Application,
+    image_id: String,
+    service: &ReconciliationService,
+) -> Result<(), Error> {
+    let environment = environment(application, service).await?;
+    let containers = service
+        .container_executor
+        .running(application.name.clone())
+        .await?;
+    let mut scaled = false;
+    for process in application.processes() {
+        let Some(ref autoscaling) = process.autoscaling else {
+            continue;
+        };
+        let instances = containers
+            .iter()
+            .filter(|container| {
+                container.process == process.name && container.state == ContainerState::Running
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if instances.is_empty() {
+            continue;
+        }
+        // Runtimes scaling the process themselves don't report its load
+        let Some(load) = service
+            .container_executor
+            .process_load(application, &process, &instances)
+            .await?
+        else {
+            continue;
+        };
+        let current = service.replicas(&application.name, &process, instances.len());
+        let desired = desired_replicas(
+            autoscaling,
+            current,
+            instances.len(),
+            load,
+            environment.resources.cpu_limit,
+        );
+        if desired != current {
+            info!(
+                "Scaling process {} of application {} from {} to {} instances ({:?})",
+                process.name, application.name, current, desired, load
+            );
+            service
+                .autoscaled_replicas
+                .lock()
+                .expect("Autoscaled replicas poisoned")
+                .insert((application.name.clone(), process.name.clone()), desired);
+            scaled = true;
+        }
+    }
+    if scaled {
+        converge(application, image_id, &environment, service, &LogReporter).await?;
+    }
+    Ok(())
+}
+
+/// Replicas bringing the load of the instances back to the targets, the most loaded metric wins.
+/// Loads within 10% of the targets keep the current replicas to avoid flapping
+fn desired_replicas(
+    autoscaling: &Autoscaling,
+    current: u8,
+    instances: usize,
+    load: ProcessLoad,
+    cpu_limit: Option<u32>,
+) -> u8 {
+    let ratios = [
+        autoscaling
+            .target_cpu
+            .zip(load.cpu)
+            .zip(cpu_limit)
+            .map(|((target, cpu), limit)| {
+                f64::from(cpu) / (f64::from(limit) * f64::from(target) / 100.0)
+            }),
+        autoscaling
+            .target_requests
+            .zip(load.requests)
+            .map(|(target, requests)| requests / instances as f64 / f64::from(target)),
+    ];
+    let desired = match ratios.into_iter().flatten().reduce(f64::max) {
+        Some(ratio) if (ratio - 1.0).abs() > 0.1 => {
+            (instances as f64 * ratio).ceil().min(f64::from(u8::MAX)) as u8
+        }
+        _ => current,
+    };
+    desired.max(autoscaling.min).min(autoscaling.max)
+}
+
+pub async fn save_secret(service: &ReconciliationService, secret: Secret) -> Result<(), Error> {
+    service.secret_repository.save(&secret).await
+}
+
+pub async fn list_secrets(service: &ReconciliationService) -> Result<Vec<String>, Error> {
+    service.secret_repository.list().await
+}
+
+pub async fn delete_secret(
+    service: &ReconciliationService,
+    secret_name: String,
+) -> Result<(), Error> {
+    service.secret_repository.delete(secret_name).await
+}
+
+pub async fn save_git_credential(
+    service: &ReconciliationService,
+    credential: GitCredential,
+) -> Result<(), Error> {
+    service.git_credential_repository.save(&credential).await
+}
+
+pub async fn list_git_credentials(service: &ReconciliationService) -> Result<Vec<String>, Error> {
+    service.git_credential_repository.list().await
+}
+
+pub async fn delete_git_credential(
+    service: &ReconciliationService,
+    credential_name: String,
+) -> Result<(), Error> {
+    service
+        .git_credential_repository
+        .delete(credential_name)
+        .await
+}
+
+pub async fn save_registry_credential(
+    service: &ReconciliationService,
+    credential: RegistryCredential,
+) -> Result<(), Error> {
+    service
+        .registry_credential_repository
+        .save(&credential)
+        .await
+}
+
+pub async fn list_registry_credentials(
+    service: &ReconciliationService,
+) -> Result<Vec<String>, Error> {
+    service.registry_credential_repository.list().await
+}
+
+pub async fn delete_registry_credential(
+    service: &ReconciliationService,
+    registry: String,
+) -> Result<(), Error> {
+    service
+        .registry_credential_repository
+        .delete(registry)
+        .await
+}
+
+/// Provision an add-on with a generated password, it is attached to applications by name
+pub async fn create_addon(
+    service: &ReconciliationService,
+    name: String,
+    kind: AddonKind,
+) -> Result<(), Error> {
+    if !resource_name(&name) {
+        return Err(anyhow!(
+            "Add-on name {} must be lowercase alphanumerics and single dashes",
+            name
+        ));
+    }
+    if service.addon_repository.get(name.clone()).await?.is_some() {
+        return Err(anyhow!("Add-on {} already exists", name));
+    }
+    let password = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect::<String>();
+    let host = service
+        .container_executor
+        .create_addon(name.clone(), kind, password.clone())
+        .await?;
+    service
+        .addon_repository
+        .save(&Addon {
+            name,
+            kind,
+            host,
+            password,
+        })
+        .await
+}
+
+pub async fn list_addons(service: &ReconciliationService) -> Result<Vec<String>, Error> {
+    service.addon_repository.list().await
+}
+
+/// Remove the add-on along with its data, once no application is attached to it
+pub async fn delete_addon(service: &ReconciliationService, name: String) -> Result<(), Error> {
+    if service.addon_repository.get(name.clone()).await?.is_none() {
+        return Err(anyhow!("Add-on {} doesn't exist", name));
+    }
+    if let Some(application) = service
+        .application_repository
+        .list()
+        .await?
+        .into_iter()
+        .find(|application| attachments(application).any(|attachment| attachment.addon == name))
+    {
+        return Err(anyhow!(
+            "Add-on {} is attached to application {}",
+            name,
+            application.name
+        ));
+    }
+    service
+        .container_executor
+        .delete_addon(name.clone())
+        .await?;
+    service.addon_repository.delete(name).await
+}
+
+/// Attach the add-on to the stored application, replacing a previous attachment of the same add-on.
+/// Its url is injected by the next reconciliation
+pub async fn attach_addon(
+    service: &ReconciliationService,
+    application_name: String,
+    attachment: AddonAttachment,
+) -> Result<(), Error> {
+    let lock = service.application_lock(&application_name);
+    let _guard = lock.lock().await;
+    let mut application = service
+        .application_repository
+        .get(application_name.clone())
+        .await?
+        .ok_or(anyhow!("Application {} doesn't exist", application_name))?;
+    let addon = service
+        .addon_repository
+        .get(attachment.addon.clone())
+        .await?
+        .ok_or(anyhow!("Add-on {} doesn't exist", attachment.addon))?;
+    // Checked now rather than failing the next reconciliation
+    let variable = attachment
+        .variable
+        .clone()
+        .unwrap_or(addon.kind.default_variable().to_string());
+    let mut taken = false;
+    for attached in attachments(&application).filter(|attached| attached.addon != attachment.addon)
+    {
+        let attached_variable = match attached.variable {
+            Some(ref variable) => Some(variable.clone()),
+            None => service
+                .addon_repository
+                .get(attached.addon.clone())
+                .await?
+                .map(|other| other.kind.default_variable().to_string()),
+        };
+        taken |= attached_variable.as_ref() == Some(&variable);
+    }
+    let configuration = application
+        .configuration
+        .get_or_insert_with(Default::default);
+    if taken
+        || configuration
+            .env
+            .as_ref()
+            .is_some_and(|env| env.contains_key(&variable))
+        || configuration
+            .secrets
+            .as_ref()
+            .is_some_and(|secrets| secrets.contains_key(&variable))
+    {
+        return Err(anyhow!(
+            "Variable {} of add-on {} is already set",
+            variable,
+            addon.name
+        ));
+    }
+    let addons = configuration.addons.get_or_insert_with(Vec::new);
+    addons.retain(|attached| attached.addon != attachment.addon);
+    addons.push(attachment);
+    service.application_repository.save(&application).await
+}
+
+/// Detach the add-on from the stored application, its url is removed by the next reconciliation
+pub async fn detach_addon(
+    service: &ReconciliationService,
+    application_name: String,
+    addon_name: String,
+) -> Result<(), Error> {
+    let lock = service.application_lock(&application_name);
+    let _guard = lock.lock().await;
+    let mut application = service
+        .application_repository
+        .get(application_name.clone())
+        .await?
+        .ok_or(anyhow!("Application {} doesn't exist", application_name))?;
+    if !attachments(&application).any(|attachment| attachment.addon == addon_name) {
+        return Err(anyhow!(
+            "Add-on {} isn't attached to application {}",
+            addon_name,
+            application_name
+        ));
+    }
+    if let Some(addons) = application
+        .configuration
+        .as_mut()
+        .and_then(|configuration| configuration.addons.as_mut())
+    {
+        addons.retain(|attachment| attachment.addon != addon_name);
+    }
+    service.application_repository.save(&application).await
+}
+
+/// Attachments made through the API are kept by a deployment that doesn't declare any
+async fn keep_attachments(
+    application: &mut Application,
+    service: &ReconciliationService,
+) -> Result<(), Error> {
+    if application
+        .configuration
+        .as_ref()
+        .is_some_and(|configuration| configuration.addons.is_some())
+    {
+        return Ok(());
+    }
+    let Some(stored) = service
+        .application_repository
+        .get(application.name.clone())
+        .await?
+    else {
+        return Ok(());
+    };
+    let attached = attachments(&stored).cloned().collect::<Vec<_>>();
+    if !attached.is_empty() {
+        application
+            .configuration
+            .get_or_insert_with(Default::default)
+            .addons = Some(attached);
+    }
+    Ok(())
+}
+
+/// Attachments aren't versioned with releases, a rollback keeps the current ones
+async fn current_attachments(
+    application: &mut Application,
+    service: &ReconciliationService,
+) -> Result<(), Error> {
+    let Some(stored) = service
+        .application_repository
+        .get(application.name.clone())
+        .await?
+    else {
+        return Ok(());
+    };
+    let attached = stored
+        .configuration
+        .and_then(|configuration| configuration.addons);
+    if attached.is_some() || application.configuration.is_some() {
+        application
+            .configuration
+            .get_or_insert_with(Default::default)
+            .addons = attached;
+    }
+    Ok(())
+}
+
+fn attachments(application: &Application) -> impl Iterator<Item = &AddonAttachment> {
+    application
+        .configuration
+        .iter()
+        .flat_map(|configuration| configuration.addons.iter().flatten())
+}
+
+/// Stored configuration and running instances of the application, `None` when unknown
+pub async fn get_application(
+    service: &ReconciliationService,
+    application_name: String,
+) -> Result<Option<ApplicationDetail>, Error> {
+    let application = service
+        .application_repository
+        .get(application_name.clone())
+        .await?;
+    let instances = service
+        .container_executor
+        .running(application_name.clone())
+        .await?;
+    let volumes = service
+        .container_executor
+        .volumes(application_name.clone())
+        .await?;
+    if application.is_none() && instances.is_empty() && volumes.is_empty() {
+        return Ok(None);
+    }
+    let declared = application
+        .as_ref()
+        .map(Application::volumes)
+        .unwrap_or_default();
+    let volumes = volumes
+        .into_iter()
+        .map(|name| VolumeDetail {
+            mount_path: declared
+                .iter()
+                .find(|volume| volume.name == name)
+                .map(|volume| volume.mount_path.clone()),
+            name,
+        })
+        .collect();
+    Ok(Some(ApplicationDetail {
+        hosts: application
+            .as_ref()
+            .map(|application| application.hosts(&service.routing_config.domain))
+            .unwrap_or_default(),
+        image_id: service
+            .application_repository
+            .image(application_name.clone())
+            .await?,
+        name: application_name,
+        application,
+        instances,
+        volumes,
+    }))
+}
+
+/// Output of the application instances, `None` when no instance matches
+pub async fn application_logs(
+    service: &ReconciliationService,
+    application_name: String,
+    options: LogOptions,
+) -> Result<Option<BoxStream<'static, Result<LogLine, Error>>>, Error> {
+    let containers = service
+        .container_executor
+        .running(application_name.clone())
+        .await?
+        .into_iter()
+        .filter(|container| {
+            options
+                .instance
+                .as_ref()
+                .is_none_or(|instance| container.id.starts_with(instance.as_str()))
+        })
+        .collect::<Vec<_>>();
+    if containers.is_empty() {
+        return Ok(None);
+    }
+    service
+        .container_executor
+        .logs(application_name, containers, &options)
+        .await
+        .map(Some)
+}
+
+/// Start an interactive command in the running instance whose id starts with `instance`,
+/// `None` when no running instance matches
+pub async fn exec(
+    service: &ReconciliationService,
+    application_name: String,
+    instance: String,
+    options: &ExecOptions,
+) -> Result<Option<ExecSession>, Error> {
+    if options.command().is_empty() {
+        return Err(anyhow!("Command to exec is empty"));
+    }
+    let containers = service
+        .container_executor
+        .running(application_name.clone())
+        .await?
+        .into_iter()
+        .filter(|container| {
+            container.state == ContainerState::Running && container.id.starts_with(&instance)
+        })
+        .collect::<Vec<_>>();
+    match containers.as_slice() {
+        [] => Ok(None),
+        [container] => service
+            .container_executor
+            .exec(application_name, container, options)
+            .await
+            .map(Some),
+        _ => Err(anyhow!(
+            "Instance {} matches {} instances, use a longer id",
+            instance,
+            containers.len()
+        )),
+    }
+}
+
+/// Run a command once with the deployed image and environment of the application,
+/// `None` when the application isn't deployed
+pub async fn run_command(
+    service: &ReconciliationService,
+    application_name: String,
+    command: Vec<String>,
+    timeout: Duration,
+) -> Result<Option<BoxStream<'static, Result<RunOutput, Error>>>, Error> {
+    if command.is_empty() {
+        return Err(anyhow!("Command to run is empty"));
+    }
+    let Some(application) = service
+        .application_repository
+        .get(application_name.clone())
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(image_id) = service
+        .application_repository
+        .image(application_name)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let environment = environment(&application, service).await?;
+    service
+        .container_executor
+        .run(&application, image_id, &environment, command, Some(timeout))
+        .await
+        .map(Some)
+}
+
 pub async fn list_applications(
     reconciliation_service: &ReconciliationService,
 ) -> Result<Vec<String>, Error> {
-    reconciliation_service
+    let stored = reconciliation_service
+        .application_repository
+        .list()
+        .await?
+        .into_iter()
+        .map(|application| application.name);
+    let running = reconciliation_service
         .container_executor
         .list_applications()
-        .await
+        .await?;
+    // Running applications are kept for the ones deployed before the repository existed
+    Ok(stored.chain(running).unique().collect())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backward")
+        .as_secs()
 }
@@ -1,4 +1,13 @@
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Process routed on the application hosts
+pub const WEB_PROCESS: &str = "web";
+/// Process of a worker application without declared processes
+pub const WORKER_PROCESS: &str = "worker";
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Application {
@@ -7,24 +16,327 @@ pub struct Application {
     pub configuration: Option<ApplicationConfig>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+impl Application {
+    /// Hosts the application is routed on, `domain` then `hosts` of the configuration,
+    /// the application name under the platform domain when there is none. Applications without web process have no host
+    pub fn hosts(&self, domain: &str) -> Vec<String> {
+        if !self.routed() {
+            return vec![];
+        }
+        let hosts = self
+            .configuration
+            .iter()
+            .flat_map(|configuration| {
+                configuration
+                    .domain
+                    .iter()
+                    .chain(configuration.hosts.iter().flatten())
+            })
+            .map(|host| match host.contains('.') {
+                true => host.to_lowercase(),
+                false => format!("{}.{}", host.to_lowercase(), domain),
+            })
+            .unique()
+            .collect::<Vec<_>>();
+        if hosts.is_empty() {
+            vec![format!("{}.{}", self.name, domain)]
+        } else {
+            hosts
+        }
+    }
+
+    /// Path prefixes the application is routed on, without trailing slash, `/` when there is none
+    pub fn paths(&self) -> Vec<String> {
+        let paths = self
+            .configuration
+            .iter()
+            .flat_map(|configuration| configuration.paths.iter().flatten())
+            .map(|path| match path.trim_end_matches('/') {
+                "" => "/".to_string(),
+                path => path.to_string(),
+            })
+            .unique()
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            vec!["/".to_string()]
+        } else {
+            paths
+        }
+    }
+
+    pub fn volumes(&self) -> Vec<Volume> {
+        self.configuration
+            .as_ref()
+            .and_then(|configuration| configuration.volumes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Declared processes, a single `web` or `worker` process of the configured replicas when there is none
+    pub fn processes(&self) -> Vec<Process> {
+        match self
+            .configuration
+            .as_ref()
+            .and_then(|configuration| configuration.processes.clone())
+        {
+            Some(processes) if !processes.is_empty() => processes,
+            _ => vec![Process {
+                name: match self.process_type() {
+                    ProcessType::Web => WEB_PROCESS.to_string(),
+                    ProcessType::Worker => WORKER_PROCESS.to_string(),
+                },
+                command: None,
+                replicas: self
+                    .configuration
+                    .as_ref()
+                    .and_then(|configuration| configuration.replicas),
+                autoscaling: self
+                    .configuration
+                    .as_ref()
+                    .and_then(|configuration| configuration.autoscaling.clone()),
+            }],
+        }
+    }
+
+    pub fn routed(&self) -> bool {
+        self.processes().iter().any(Process::routed)
+    }
+
+    /// Health check of the instances of the process, declared processes other than `web` are not checked
+    pub fn healthcheck(&self, process: &Process) -> Option<&HealthCheck> {
+        let configuration = self.configuration.as_ref()?;
+        if process.routed() || configuration.processes.as_ref().is_none_or(Vec::is_empty) {
+            configuration.healthcheck.as_ref()
+        } else {
+            None
+        }
+    }
+
+    pub fn process_type(&self) -> ProcessType {
+        self.configuration
+            .as_ref()
+            .and_then(|configuration| configuration.process_type)
+            .unwrap_or_default()
+    }
+
+    /// Changes with anything an instance of the process is created from besides its image,
+    /// not with replicas nor autoscaling which don't change the instances
+    pub fn instance_checksum(&self, process: &Process, environment: &Environment) -> String {
+        let configuration = self.configuration.as_ref();
+        let spec = serde_json::json!({
+            "environment": environment.checksum(),
+            "resources": environment.resources,
+            "volumes": self.volumes(),
+            "command": process.command,
+            "routed": process.routed(),
+            "domain": configuration.and_then(|configuration| configuration.domain.as_ref()),
+            "hosts": configuration.and_then(|configuration| configuration.hosts.as_ref()),
+            "paths": configuration.and_then(|configuration| configuration.paths.as_ref()),
+            "strip_prefix": self.strip_prefix(),
+            "exposed_port": configuration.and_then(|configuration| configuration.exposed_port),
+            "tls": configuration.and_then(|configuration| configuration.tls.as_ref()),
+            "healthcheck": self.healthcheck(process),
+        });
+        checksum(spec.to_string())
+    }
+
+    pub fn strip_prefix(&self) -> bool {
+        self.configuration
+            .as_ref()
+            .and_then(|configuration| configuration.strip_prefix)
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct ApplicationConfig {
+    /// Default to `Web`, for applications without declared processes
+    pub process_type: Option<ProcessType>,
+    /// Processes sharing the application image, Procfile style, only the `web` one is routed
+    pub processes: Option<Vec<Process>>,
+    /// Subdomain of the platform domain
     pub domain: Option<String>,
+    /// Additional hosts, subdomains of the platform domain or fully qualified domains when they contain a dot
+    pub hosts: Option<Vec<String>>,
+    /// Path prefixes routed to the application on each of its hosts, all paths when unset
+    pub paths: Option<Vec<String>>,
+    /// Remove the matched path prefix before forwarding requests, default to false
+    pub strip_prefix: Option<bool>,
     pub exposed_port: Option<u16>,
+    /// For applications without declared processes, default to 1
+    pub replicas: Option<u8>,
+    /// For applications without declared processes, replaces `replicas`
+    pub autoscaling: Option<Autoscaling>,
+    pub env: Option<HashMap<String, String>>,
+    /// Environment variable name to the name of the stored secret holding its value
+    pub secrets: Option<HashMap<String, String>>,
+    /// Add-ons whose connection url is injected in the instances environment
+    pub addons: Option<Vec<AddonAttachment>>,
+    pub healthcheck: Option<HealthCheck>,
+    /// Serve the application over https, plain http when unset
+    pub tls: Option<Tls>,
+    pub resources: Option<Resources>,
+    /// Command run once with the new image before its instances are started, ie database migrations.
+    /// A failure aborts the deployment
+    pub release: Option<Vec<String>>,
+    /// Mounted in every instance and kept across deployments
+    pub volumes: Option<Vec<Volume>>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Process {
+    /// Lowercase alphanumerics and dashes, `web` for the routed process
+    pub name: String,
+    /// Command of the instances replacing the one of the image, its entrypoint is kept (buildpacks launcher)
+    pub command: Option<Vec<String>>,
+    /// Default to 1
     pub replicas: Option<u8>,
+    /// Replaces `replicas`
+    pub autoscaling: Option<Autoscaling>,
+}
+
+impl Process {
+    pub fn routed(&self) -> bool {
+        self.name == WEB_PROCESS
+    }
+
+    /// Fixed replicas, the minimum ones when autoscaled
+    pub fn replicas(&self) -> u8 {
+        match self.autoscaling {
+            Some(ref autoscaling) => autoscaling.min,
+            None => self.replicas.unwrap_or(1),
+        }
+    }
+}
+
+/// Replicas following the load of the instances, between `min` and `max`, with at least one target
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Autoscaling {
+    pub min: u8,
+    pub max: u8,
+    /// Average cpu usage of the instances, in percent of their cpu limit
+    pub target_cpu: Option<u8>,
+    /// Average requests per second handled by each instance, for the `web` process only
+    pub target_requests: Option<u32>,
+}
+
+/// Load measured on the running instances of a process
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessLoad {
+    /// Average cpu usage of an instance in millicores
+    pub cpu: Option<u32>,
+    /// Requests per second handled by all the instances
+    pub requests: Option<f64>,
+}
+
+/// Named storage kept until the application is destroyed with `purge_volumes`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Volume {
+    /// Lowercase alphanumerics and dashes
+    pub name: String,
+    pub mount_path: String,
+    /// In MiB, only used for Kubernetes claims. Default to 1024
+    pub size: Option<u32>,
+}
+
+impl Volume {
+    pub fn size(&self) -> u32 {
+        self.size.unwrap_or(1024)
+    }
+}
+
+/// Cpu in millicores and memory in MiB of each instance, limits default to the platform ones
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Resources {
+    pub cpu_limit: Option<u32>,
+    pub memory_limit: Option<u32>,
+    /// Reserved for the instance, no more than its limit
+    pub cpu_request: Option<u32>,
+    pub memory_request: Option<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AddonAttachment {
+    pub addon: String,
+    /// Environment variable of the connection url, default to `DATABASE_URL` or `REDIS_URL` depending on the add-on kind
+    pub variable: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum ProcessType {
+    /// Http server routed by Traefik on the application hosts
+    #[default]
+    Web,
+    /// Background process, without exposed port nor routing
+    Worker,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Tls {
+    /// Certificate issued by the ACME resolver of the platform
+    Acme,
+    /// Names of the stored secrets holding the PEM certificate chain and private key
+    Certificate { certificate: String, key: String },
+    /// Default self-signed certificate of Traefik
+    SelfSigned,
+}
+
+/// Check a new instance must pass before an outdated one is stopped during a rolling update
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HealthCheck {
+    pub probe: HealthProbe,
+    /// In seconds, default to 10
+    pub interval: Option<u64>,
+    /// In seconds, default to 5
+    pub timeout: Option<u64>,
+    /// Consecutive failures before the instance is unhealthy, default to 3
+    pub retries: Option<u32>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum HealthProbe {
+    /// Port default to the exposed port of the application
+    Http {
+        path: String,
+        port: Option<u16>,
+    },
+    Tcp {
+        port: Option<u16>,
+    },
+    Command {
+        command: Vec<String>,
+    },
+}
+
+impl HealthCheck {
+    pub fn interval(&self) -> u64 {
+        self.interval.unwrap_or(10)
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout.unwrap_or(5)
+    }
+
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(3)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub enum ApplicationSource {
     DockerImage {
+        /// Pulled with the stored credential of its registry host when there is one
         image: String,
         pull: bool,
-        // TODO repository and credentials
     },
     Git {
         remote: String,
         dockerfile: Option<String>,
-        // TODO credentials
+        /// Branch, tag or commit SHA to build, default branch when unset
+        reference: Option<String>,
+        /// Directory of the sources to build, relative to the repository root
+        directory: Option<String>,
+        /// Name of the stored git credential to clone a private repository
+        credential: Option<String>,
     },
     LocalRepo {
         path: String,
@@ -32,9 +344,309 @@ pub enum ApplicationSource {
     },
 }
 
+impl ApplicationSource {
+    /// Registry host of a docker image source, `docker.io` for images without one
+    pub fn registry(&self) -> Option<String> {
+        let ApplicationSource::DockerImage { ref image, .. } = self else {
+            return None;
+        };
+        Some(match image.split_once('/') {
+            Some((host, _)) if host.contains(['.', ':']) || host == "localhost" => host.to_string(),
+            _ => "docker.io".to_string(),
+        })
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Container {
     pub id: String,
+    /// Name of the process the instance runs
+    pub process: String,
     pub started_at: u64,
     pub image_id: String,
+    pub state: ContainerState,
+    pub restart_count: u32,
+    /// `None` when the runtime doesn't check the instance health
+    pub health: Option<Health>,
+    /// Instance checksum of the application the instance started with, `None` when the runtime rolls out changes itself
+    pub checksum: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ContainerState {
+    Pending,
+    Running,
+    Restarting,
+    Exited,
+}
+
+/// Stored configuration of an application along with its running instances
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApplicationDetail {
+    pub name: String,
+    pub application: Option<Application>,
+    pub hosts: Vec<String>,
+    pub image_id: Option<String>,
+    pub instances: Vec<Container>,
+    pub volumes: Vec<VolumeDetail>,
+}
+
+/// Volume kept by the runtime for an application
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VolumeDetail {
+    pub name: String,
+    /// `None` when the volume is no longer declared by the application
+    pub mount_path: Option<String>,
+}
+
+/// Selection of the application output to read
+#[derive(Clone, Default, Deserialize)]
+pub struct LogOptions {
+    /// Keep streaming new lines until the client goes away
+    #[serde(default)]
+    pub follow: bool,
+    /// Number of lines to start from on each instance, all when unset
+    pub tail: Option<u32>,
+    /// Unix timestamp in seconds of the oldest line
+    pub since: Option<u64>,
+    /// Only read the instances whose id starts with this prefix
+    pub instance: Option<String>,
+}
+
+/// Interactive command to run in an instance
+#[derive(Clone, Deserialize)]
+pub struct ExecOptions {
+    /// Split on whitespaces, default to `/bin/sh`
+    pub command: Option<String>,
+    /// Allocate a terminal, stdout and stderr are merged in it
+    #[serde(default)]
+    pub tty: bool,
+    /// Attach the stdin of the command
+    #[serde(default)]
+    pub stdin: bool,
+}
+
+impl ExecOptions {
+    pub fn command(&self) -> Vec<String> {
+        self.command
+            .as_deref()
+            .unwrap_or("/bin/sh")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct TerminalSize {
+    pub width: u16,
+    pub height: u16,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LogLine {
+    pub instance: String,
+    pub message: String,
+}
+
+/// Output of a command run once in a new instance of the application image
+#[derive(Clone, Serialize, Deserialize)]
+pub enum RunOutput {
+    Line(String),
+    /// Last output of the command, with its exit code
+    Exited(i64),
+    /// Last output of a command killed after its timeout
+    TimedOut,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Health {
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Deployment {
+    pub id: String,
+    pub application: String,
+    pub phase: DeploymentPhase,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub finished_at: Option<u64>,
+    pub error: Option<String>,
+    /// Commit SHA of the built sources, for git sources
+    pub commit: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum DeploymentPhase {
+    Pending,
+    Cloning,
+    Building,
+    Releasing,
+    RollingOut,
+    Done,
+    Failed,
+}
+
+/// Application configuration and image of a successful deployment
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub version: u32,
+    pub application: Application,
+    pub image_id: String,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Secret {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum AddonKind {
+    Postgres,
+    Redis,
+}
+
+impl AddonKind {
+    pub fn image(&self) -> &'static str {
+        match self {
+            AddonKind::Postgres => "postgres:16-alpine",
+            AddonKind::Redis => "redis:7-alpine",
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            AddonKind::Postgres => 5432,
+            AddonKind::Redis => 6379,
+        }
+    }
+
+    /// Directory of the data kept in a volume
+    pub fn data_path(&self) -> &'static str {
+        match self {
+            AddonKind::Postgres => "/var/lib/postgresql/data",
+            AddonKind::Redis => "/data",
+        }
+    }
+
+    pub fn default_variable(&self) -> &'static str {
+        match self {
+            AddonKind::Postgres => "DATABASE_URL",
+            AddonKind::Redis => "REDIS_URL",
+        }
+    }
+}
+
+/// Service provisioned by the platform with a generated password. Not `Debug` so the password can't be logged
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Addon {
+    pub name: String,
+    pub kind: AddonKind,
+    /// Host the add-on is reachable at from the application instances
+    pub host: String,
+    pub password: String,
+}
+
+impl Addon {
+    pub fn url(&self) -> String {
+        match self.kind {
+            AddonKind::Postgres => format!(
+                "postgres://postgres:{}@{}:{}/postgres",
+                self.password,
+                self.host,
+                self.kind.port()
+            ),
+            AddonKind::Redis => format!(
+                "redis://:{}@{}:{}",
+                self.password,
+                self.host,
+                self.kind.port()
+            ),
+        }
+    }
+}
+
+/// Named credential to clone private git repositories. Not `Debug` so keys and tokens can't be logged
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GitCredential {
+    pub name: String,
+    pub auth: GitAuth,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum GitAuth {
+    /// PEM private key, user default to the one of the remote url
+    Ssh {
+        private_key: String,
+        passphrase: Option<String>,
+        username: Option<String>,
+    },
+    Https {
+        username: String,
+        token: String,
+    },
+}
+
+/// Credential of an image registry, keyed by its host. Not `Debug` so passwords can't be logged
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegistryCredential {
+    pub registry: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl RegistryCredential {
+    /// Docker hub credentials are registered under its legacy index url
+    pub fn server_address(&self) -> String {
+        match self.registry.as_str() {
+            "docker.io" => "https://index.docker.io/v1/".to_string(),
+            registry => registry.to_string(),
+        }
+    }
+}
+
+/// Resolved credentials to fetch the sources of the application
+#[derive(Clone, Default)]
+pub struct SourceCredentials {
+    pub git: Option<GitCredential>,
+    pub registry: Option<RegistryCredential>,
+}
+
+/// Resolved environment of the application instances. Not `Debug` so secret values can't be logged
+#[derive(Clone, Default)]
+pub struct Environment {
+    pub variables: HashMap<String, String>,
+    pub secrets: HashMap<String, String>,
+    /// Certificate the application is served with, for `Tls::Certificate`
+    pub certificate: Option<Certificate>,
+    pub resources: Resources,
+}
+
+impl Environment {
+    /// Changes with any variable or secret value, not with resources nor certificate
+    pub fn checksum(&self) -> String {
+        let variables = self
+            .variables
+            .iter()
+            .chain(self.secrets.iter())
+            .collect::<BTreeMap<_, _>>();
+        checksum(serde_json::to_string(&variables).unwrap_or_default())
+    }
+}
+
+/// Hex SHA-256 of the content, stable across builds unlike the std hashers as it is stored in runtime labels
+pub fn checksum(content: impl AsRef<[u8]>) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// PEM certificate chain and private key
+#[derive(Clone)]
+pub struct Certificate {
+    pub certificate: String,
+    pub key: String,
 }
@@ -1,22 +1,166 @@
-use super::model::{Application, Container};
+use super::model::{
+    Addon, AddonKind, Application, Container, Deployment, DeploymentPhase, Environment, ExecOptions, GitCredential, Health, LogLine, LogOptions, Process, ProcessLoad, RegistryCredential, Release, RunOutput, Secret,
+    SourceCredentials, TerminalSize,
+};
 use anyhow::Error;
 use async_trait::async_trait;
+use futures::{future::BoxFuture, stream::BoxStream};
+use std::{pin::Pin, time::Duration};
+use tokio::{io::AsyncWrite, sync::mpsc};
 
 #[async_trait]
 pub trait ContainerExecutor {
-    async fn register_image(&self, application: &Application) -> Result<String, Error>;
+    async fn register_image(&self, application: &Application, credentials: &SourceCredentials, reporter: &dyn DeploymentReporter) -> Result<String, Error>;
 
-    async fn register_application(&self, application: &Application, image_id: String) -> Result<Vec<Container>, Error>;
+    /// Create the volumes of the application, refusing existing ones that belong to another application
+    async fn register_volumes(&self, application: &Application) -> Result<(), Error>;
 
-    async fn delete_application(&self, application: String) -> Result<(), Error>;
+    async fn register_application(&self, application: &Application, image_id: String, environment: &Environment) -> Result<Vec<Container>, Error>;
+
+    async fn delete_application(&self, application: String, purge_volumes: bool) -> Result<(), Error>;
+
+    /// Names of the volumes kept for the application
+    async fn volumes(&self, application_name: String) -> Result<Vec<String>, Error>;
 
     async fn running(&self, application: String) -> Result<Vec<Container>, Error>;
 
-    async fn start_instance(&self, application: &Application, image_id: String) -> Result<Container, Error>;
+    async fn start_instance(&self, application: &Application, process: &Process, image_id: String, environment: &Environment) -> Result<Container, Error>;
 
     async fn stop_instance(&self, application_name: String, container: &Container) -> Result<(), Error>;
 
+    async fn instance_health(&self, application_name: String, container: &Container) -> Result<Health, Error>;
+
+    /// Output of the given instances, interleaved as it is read
+    async fn logs(&self, application_name: String, containers: Vec<Container>, options: &LogOptions) -> Result<BoxStream<'static, Result<LogLine, Error>>, Error>;
+
+    /// Run the command once in a new instance of the image, removed once the command ends.
+    /// Output lines are streamed as they are read, followed by the way the command ended
+    async fn run(&self, application: &Application, image_id: String, environment: &Environment, command: Vec<String>, timeout: Option<Duration>) -> Result<BoxStream<'static, Result<RunOutput, Error>>, Error>;
+
+    /// Start an interactive command in a running instance
+    async fn exec(&self, application_name: String, container: &Container, options: &ExecOptions) -> Result<ExecSession, Error>;
+
     async fn list_applications(&self) -> Result<Vec<String>, Error>;
 
     async fn ensure_routing(&self) -> Result<(), Error>;
+
+    /// Whether the runtime scales autoscaled processes itself, their replicas are then left to it
+    fn scales_processes(&self) -> bool;
+
+    /// Load of the running instances of the autoscaled process, `None` when the runtime scales it itself
+    async fn process_load(&self, application: &Application, process: &Process, containers: &[Container]) -> Result<Option<ProcessLoad>, Error>;
+
+    /// Provision the add-on with its data kept in a volume, return the host it is reachable at from the application instances
+    async fn create_addon(&self, name: String, kind: AddonKind, password: String) -> Result<String, Error>;
+
+    /// Remove the add-on along with its data
+    async fn delete_addon(&self, name: String) -> Result<(), Error>;
+}
+
+/// Command running in an instance, attached to the client
+pub struct ExecSession {
+    /// Stdout and stderr of the command, as they are read
+    pub output: BoxStream<'static, Result<Vec<u8>, Error>>,
+    /// `None` when stdin isn't attached
+    pub input: Option<Pin<Box<dyn AsyncWrite + Send>>>,
+    /// Terminal size changes, `None` without tty
+    pub resize: Option<mpsc::Sender<TerminalSize>>,
+    /// Exit code of the command, to await once its output ended
+    pub exit_code: BoxFuture<'static, Result<Option<i64>, Error>>,
+}
+
+/// Desired state of the deployed applications, kept across restarts
+#[async_trait]
+pub trait ApplicationRepository {
+    async fn save(&self, application: &Application) -> Result<(), Error>;
+
+    async fn get(&self, application_name: String) -> Result<Option<Application>, Error>;
+
+    async fn list(&self) -> Result<Vec<Application>, Error>;
+
+    async fn delete(&self, application_name: String) -> Result<(), Error>;
+
+    async fn save_image(&self, application_name: String, image_id: String) -> Result<(), Error>;
+
+    async fn image(&self, application_name: String) -> Result<Option<String>, Error>;
+}
+
+#[async_trait]
+pub trait DeploymentRepository {
+    async fn save(&self, deployment: &Deployment) -> Result<(), Error>;
+
+    async fn get(&self, deployment_id: String) -> Result<Option<Deployment>, Error>;
+
+    async fn append_log(&self, deployment_id: String, line: String) -> Result<(), Error>;
+
+    async fn logs(&self, deployment_id: String) -> Result<Vec<String>, Error>;
+}
+
+#[async_trait]
+pub trait ReleaseRepository {
+    async fn save(&self, release: &Release) -> Result<(), Error>;
+
+    /// Releases of the application ordered by version
+    async fn list(&self, application_name: String) -> Result<Vec<Release>, Error>;
+
+    async fn delete(&self, application_name: String) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait SecretRepository {
+    async fn save(&self, secret: &Secret) -> Result<(), Error>;
+
+    async fn get(&self, secret_name: String) -> Result<Option<Secret>, Error>;
+
+    /// Names of the stored secrets, values are only read one by one with `get`
+    async fn list(&self) -> Result<Vec<String>, Error>;
+
+    async fn delete(&self, secret_name: String) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait GitCredentialRepository {
+    async fn save(&self, credential: &GitCredential) -> Result<(), Error>;
+
+    async fn get(&self, credential_name: String) -> Result<Option<GitCredential>, Error>;
+
+    /// Names of the stored credentials, keys and tokens are only read one by one with `get`
+    async fn list(&self) -> Result<Vec<String>, Error>;
+
+    async fn delete(&self, credential_name: String) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait RegistryCredentialRepository {
+    async fn save(&self, credential: &RegistryCredential) -> Result<(), Error>;
+
+    async fn get(&self, registry: String) -> Result<Option<RegistryCredential>, Error>;
+
+    /// Hosts of the registries with a stored credential
+    async fn list(&self) -> Result<Vec<String>, Error>;
+
+    async fn delete(&self, registry: String) -> Result<(), Error>;
+}
+
+#[async_trait]
+pub trait AddonRepository {
+    async fn save(&self, addon: &Addon) -> Result<(), Error>;
+
+    async fn get(&self, addon_name: String) -> Result<Option<Addon>, Error>;
+
+    /// Names of the provisioned add-ons, passwords are only read one by one with `get`
+    async fn list(&self) -> Result<Vec<String>, Error>;
+
+    async fn delete(&self, addon_name: String) -> Result<(), Error>;
+}
+
+/// Progress notifications of a running deployment
+#[async_trait]
+pub trait DeploymentReporter: Sync + Send {
+    async fn phase(&self, phase: DeploymentPhase);
+
+    async fn log(&self, line: String);
+
+    /// Commit SHA resolved from the git source
+    async fn commit(&self, sha: String);
 }
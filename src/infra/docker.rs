@@ -1,39 +1,52 @@
 use std::{
-    collections::HashMap, fs::remove_dir_all, path::Path, time::{SystemTime, UNIX_EPOCH}
+    collections::HashMap, fs::remove_dir_all, hash::{DefaultHasher, Hash, Hasher}, path::Path, sync::Mutex, time::{Duration, Instant, SystemTime, UNIX_EPOCH}
 };
 
 use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
 use bollard::{
+    auth::DockerCredentials,
     container::{
         AttachContainerOptions, AttachContainerResults, Config, CreateContainerOptions,
-        ListContainersOptions, LogOutput, NetworkingConfig, RemoveContainerOptions,
-        StartContainerOptions, UploadToContainerOptions,
-    }, image::{BuildImageOptions, CreateImageOptions}, network::{CreateNetworkOptions, ListNetworksOptions}, secret::{
-        BuildInfoAux, CreateImageInfo, EndpointSettings, HostConfig, PortBinding, RestartPolicy, RestartPolicyNameEnum
-    }, Docker
+        ListContainersOptions, LogOutput, LogsOptions, NetworkingConfig, RemoveContainerOptions,
+        StartContainerOptions, StatsOptions, UploadToContainerOptions, WaitContainerOptions,
+    }, exec::{CreateExecOptions, ResizeExecOptions, StartExecOptions, StartExecResults}, image::{BuildImageOptions, CreateImageOptions}, network::{CreateNetworkOptions, ListNetworksOptions}, secret::{
+        BuildInfoAux, ContainerStateStatusEnum, CreateImageInfo, EndpointSettings, HealthConfig, HealthStatusEnum, HostConfig, ImageInspect, PortBinding, RestartPolicy, RestartPolicyNameEnum
+    }, volume::{CreateVolumeOptions, ListVolumesOptions, RemoveVolumeOptions}, Docker
 };
 use bytes::{BufMut, BytesMut};
 use flate2::{write::GzEncoder, Compression};
-use futures::{StreamExt, TryStreamExt};
-use git2::Repository;
+use futures::{stream::BoxStream, FutureExt, StreamExt, TryStreamExt};
 use itertools::Itertools;
 use log::{info, warn};
 use map_macro::hash_map;
 use rand::{distributions::Alphanumeric, Rng};
+use tokio::sync::mpsc;
 
 use crate::{
     config::{DockerConfig, RoutingConfig},
     domain::{
-        model::{Application, ApplicationSource, Container},
-        port::ContainerExecutor,
+        model::{
+            AddonKind, Application, ApplicationSource, Container, ContainerState, DeploymentPhase, Environment, Health,
+            checksum, ExecOptions, HealthCheck, HealthProbe, LogLine, LogOptions, Process, ProcessLoad, Resources, RunOutput, SourceCredentials, TerminalSize, WEB_PROCESS,
+        },
+        port::{ContainerExecutor, DeploymentReporter, ExecSession},
+    },
+    infra::{
+        git,
+        routing::{Route, ACME_RESOLVER, TRAEFIK_DYNAMIC_DIRECTORY},
     },
 };
 
+const TRAEFIK_CONTAINER: &str = "cleverclown_traefik";
+const BUILDPACK_BUILDER: &str = "heroku/builder:24";
+
 pub struct DockerContainerExecutor {
     pub docker_config: DockerConfig,
     pub routing_config: RoutingConfig,
     pub docker: Docker,
+    /// Last requests count read from traefik for each application, the rate is measured between two reads
+    pub request_samples: Mutex<HashMap<String, (f64, Instant)>>,
 }
 
 #[async_trait]
@@ -41,36 +54,75 @@ impl ContainerExecutor for DockerContainerExecutor {
     async fn running(
         &self,
         application_name: String,
-    ) -> Result<Vec<crate::domain::model::Container>, anyhow::Error> {
+    ) -> Result<Vec<Container>, Error> {
         let containers = self
             .docker
             .list_containers(Some(ListContainersOptions {
+                // Exited containers are listed so they can be reported and replaced
+                all: true,
                 filters: hash_map! {
                     "label" => vec![format!("cleverclown.application.name={}", application_name).as_str()]
                 },
                 ..Default::default()
             }))
             .await?;
-        Ok(containers
-            .into_iter()
-            .map(|docker_container| Container {
-                id: docker_container
-                    .id
-                    .or(docker_container
-                        .names
-                        .and_then(|names| names.first().cloned()))
-                    .unwrap_or(application_name.clone()),
+        let application_name = &application_name;
+        futures::future::try_join_all(containers.into_iter().map(|docker_container| async move {
+            let id = docker_container
+                .id
+                .or(docker_container
+                    .names
+                    .and_then(|names| names.first().cloned()))
+                .unwrap_or(application_name.clone());
+            let inspect = self
+                .docker
+                .inspect_container(id.as_str(), None)
+                .await
+                .context(format!("Error while inspecting container {}", id))?;
+            let state = inspect.state.unwrap_or_default();
+            Ok(Container {
+                // Instances started before processes were introduced run the web one
+                process: docker_container
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get("cleverclown.process").cloned())
+                    .unwrap_or(WEB_PROCESS.to_string()),
                 image_id: docker_container.image.unwrap(),
                 started_at: u64::try_from(docker_container.created.unwrap()).unwrap(), // TODO ???
+                state: match state.status {
+                    Some(ContainerStateStatusEnum::CREATED) => ContainerState::Pending,
+                    Some(ContainerStateStatusEnum::RUNNING) | Some(ContainerStateStatusEnum::PAUSED) => ContainerState::Running,
+                    Some(ContainerStateStatusEnum::RESTARTING) => ContainerState::Restarting,
+                    _ => ContainerState::Exited,
+                },
+                restart_count: inspect.restart_count.and_then(|count| u32::try_from(count).ok()).unwrap_or(0),
+                health: state.health.and_then(|health| health.status).and_then(|status| match status {
+                    HealthStatusEnum::STARTING => Some(Health::Starting),
+                    HealthStatusEnum::HEALTHY => Some(Health::Healthy),
+                    HealthStatusEnum::UNHEALTHY => Some(Health::Unhealthy),
+                    _ => None,
+                }),
+                checksum: docker_container
+                    .labels
+                    .as_ref()
+                    .and_then(|labels| labels.get("cleverclown.checksum").cloned()),
+                id,
             })
-            .collect())
+        }))
+        .await
     }
 
-    async fn register_image(&self, application: &Application) -> Result<String, Error> {
+    async fn register_image(&self, application: &Application, credentials: &SourceCredentials, reporter: &dyn DeploymentReporter) -> Result<String, Error> {
         match application.source {
             ApplicationSource::DockerImage { ref image, pull } => {
                 if pull {
-                    info!("Pull image {}", image.as_str());
+                    reporter.log(format!("Pull image {}", image.as_str())).await;
+                    let docker_credentials = credentials.registry.as_ref().map(|credential| DockerCredentials {
+                        username: Some(credential.username.clone()),
+                        password: Some(credential.password.clone()),
+                        serveraddress: Some(credential.server_address()),
+                        ..Default::default()
+                    });
                     self.docker
                         .create_image(
                             Some(CreateImageOptions {
@@ -78,7 +130,7 @@ impl ContainerExecutor for DockerContainerExecutor {
                                 ..Default::default()
                             }),
                             None,
-                            None,
+                            docker_credentials,
                         )
                         .try_collect::<Vec<CreateImageInfo>>()
                         .await
@@ -110,24 +162,54 @@ impl ContainerExecutor for DockerContainerExecutor {
             ApplicationSource::Git {
                 ref remote,
                 ref dockerfile,
+                ref reference,
+                ref directory,
+                credential: _,
             } => {
                 let local_dir = format!("{}/{}", self.docker_config.source_directory, application.name);
                 if Path::new(local_dir.as_str()).exists() {
                     remove_dir_all(Path::new(local_dir.as_str()))?;
                 }
-                info!("Clone git repository {}", remote);
-                let _repository = Repository::clone(remote.as_str(), local_dir.as_str())?;
+                reporter.phase(DeploymentPhase::Cloning).await;
+                match reference {
+                    Some(reference) => reporter.log(format!("Clone git repository {} at {}", remote, reference)).await,
+                    None => reporter.log(format!("Clone git repository {}", remote)).await,
+                }
+                let commit = git::clone(
+                    remote.as_str(),
+                    reference.as_deref(),
+                    Path::new(local_dir.as_str()),
+                    credentials.git.as_ref(),
+                    Path::new(self.docker_config.known_hosts.as_str()),
+                )?;
+                reporter.log(format!("Checked out commit {}", commit)).await;
+                reporter.commit(commit.clone()).await;
+                // Images are tagged with their commit and build inputs, a commit already built the same way is not built again
+                let tag = format!(
+                    "{}:{}-{}",
+                    application.name,
+                    commit,
+                    build_checksum(directory.as_deref(), dockerfile.as_deref())
+                );
+                if let Ok(ImageInspect { id: Some(image_id), .. }) = self.docker.inspect_image(tag.as_str()).await {
+                    reporter.log(format!("Reuse image {} already built for commit {}", image_id, commit)).await;
+                    return Ok(image_id);
+                }
+                let source_dir = git::source_directory(Path::new(local_dir.as_str()), directory.as_deref())?;
+                let source_dir = source_dir.to_string_lossy().to_string();
+                reporter.phase(DeploymentPhase::Building).await;
                 match dockerfile {
                     Some(ref dockerfile) => {
                         self.build_docker_image(
-                            local_dir,
-                            application.name.clone(),
+                            source_dir,
+                            tag,
                             dockerfile.clone(),
+                            reporter,
                         )
                         .await
                     }
                     None => {
-                        self.build_image_buildpack(local_dir, application.name.clone())
+                        self.build_image_buildpack(source_dir, tag, reporter)
                             .await
                     }
                 }
@@ -135,63 +217,154 @@ impl ContainerExecutor for DockerContainerExecutor {
             ApplicationSource::LocalRepo {
                 ref path,
                 ref dockerfile,
-            } => match dockerfile {
-                Some(ref dockerfile) => {
-                    self.build_docker_image(
-                        path.clone(),
-                        application.name.clone(),
-                        dockerfile.clone(),
-                    )
-                    .await
-                }
-                None => {
-                    self.build_image_buildpack(path.clone(), application.name.clone())
+            } => {
+                reporter.phase(DeploymentPhase::Building).await;
+                match dockerfile {
+                    Some(ref dockerfile) => {
+                        self.build_docker_image(
+                            path.clone(),
+                            application.name.clone(),
+                            dockerfile.clone(),
+                            reporter,
+                        )
                         .await
+                    }
+                    None => {
+                        self.build_image_buildpack(path.clone(), application.name.clone(), reporter)
+                            .await
+                    }
                 }
-            },
+            }
+        }
+    }
+
+    async fn register_volumes(&self, application: &Application) -> Result<(), Error> {
+        // Volumes are created beforehand to be labelled, a bind would create them without labels
+        for volume in application.volumes() {
+            // Creating an existing volume returns it untouched, its labels tell whether it belongs to the application
+            let created = self
+                .docker
+                .create_volume(CreateVolumeOptions {
+                    name: docker_volume_name(application.name.as_str(), volume.name.as_str()),
+                    labels: hash_map! {
+                        String::from("cleverclown.application.name") => application.name.clone(),
+                        String::from("cleverclown.volume") => volume.name.clone()
+                    },
+                    ..Default::default()
+                })
+                .await
+                .with_context(|| format!("Error while creating volume {}", volume.name))?;
+            if created.labels.get("cleverclown.application.name") != Some(&application.name) || created.labels.get("cleverclown.volume") != Some(&volume.name) {
+                return Err(anyhow!("Volume {} already exists and doesn't belong to application {}", created.name, application.name));
+            }
         }
+        Ok(())
     }
 
-    async fn register_application(&self, application: &Application, _image_id: String) -> Result<Vec<Container>, Error> {
+    async fn register_application(&self, application: &Application, _image_id: String, _environment: &Environment) -> Result<Vec<Container>, Error> {
         // Docker runtime doesn't support application definition
         self.running(application.name.clone()).await
     }
 
-    async fn delete_application(&self, _application: String) -> Result<(), Error> {
-        // Docker runtime doesn't support application definition
+    async fn delete_application(&self, application: String, purge_volumes: bool) -> Result<(), Error> {
+        // Docker runtime doesn't support application definition, only its certificate is kept by traefik
+        self.sync_certificate(Route::named(&application).router.as_str(), None).await?;
+        if purge_volumes {
+            for volume in self.volumes(application.clone()).await? {
+                self.docker
+                    .remove_volume(docker_volume_name(application.as_str(), volume.as_str()).as_str(), None)
+                    .await
+                    .with_context(|| format!("Error while removing volume {}", volume))?;
+            }
+        }
         Ok(())
     }
 
-    async fn start_instance(&self, application: &Application, image_id: String) -> Result<Container, Error> {
-        let exposed_port = match application
+    async fn volumes(&self, application_name: String) -> Result<Vec<String>, Error> {
+        let volumes = self
+            .docker
+            .list_volumes(Some(ListVolumesOptions {
+                filters: hash_map! {
+                    String::from("label") => vec![format!("cleverclown.application.name={}", application_name)]
+                },
+            }))
+            .await?;
+        Ok(volumes
+            .volumes
+            .into_iter()
+            .flatten()
+            .filter_map(|volume| volume.labels.get("cleverclown.volume").cloned())
+            .sorted()
+            .collect())
+    }
+
+    async fn start_instance(&self, application: &Application, process: &Process, image_id: String, environment: &Environment) -> Result<Container, Error> {
+        let configured_port = application
             .configuration
             .as_ref()
-            .and_then(|configuration| configuration.exposed_port)
-        {
-            Some(ref port) => port.clone(),
-            None => self.extract_min_exposed_port(image_id.as_str()).await?,
+            .and_then(|configuration| configuration.exposed_port);
+        let route = Route::new(application, &self.routing_config);
+        // Only the web process is routed, others expose the configured port if any
+        let (exposed_port, labels) = match process.routed() {
+            true => {
+                let exposed_port = match configured_port {
+                    Some(port) => port,
+                    None => self.extract_min_exposed_port(image_id.as_str()).await?,
+                };
+                self.sync_certificate(
+                    route.router.as_str(),
+                    environment.certificate.as_ref().map(|certificate| route.traefik_certificate_config(certificate)),
+                )
+                .await?;
+                (Some(exposed_port), route.docker_labels(exposed_port))
+            }
+            false if application.routed() => (configured_port, HashMap::new()),
+            false => {
+                self.sync_certificate(route.router.as_str(), None).await?;
+                (configured_port, HashMap::new())
+            }
         };
-
         let config = Config {
             image: Some(image_id.clone()),
-            exposed_ports: Some(hash_map! {
-                format!("{}/tcp", exposed_port) => HashMap::new()
+            env: Some(
+                environment
+                    .variables
+                    .iter()
+                    .chain(environment.secrets.iter())
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect(),
+            ),
+            exposed_ports: exposed_port.map(|exposed_port| {
+                hash_map! {
+                    format!("{}/tcp", exposed_port) => HashMap::new()
+                }
             }),
+            cmd: process.command.clone(),
+            healthcheck: application
+                .healthcheck(process)
+                .map(|healthcheck| docker_healthcheck(healthcheck, exposed_port))
+                .transpose()?,
             host_config: Some(HostConfig {
                 // port_bindings: Some(port_binding),
                 restart_policy: Some(RestartPolicy {
                     name: Some(RestartPolicyNameEnum::ON_FAILURE),
                     maximum_retry_count: Some(3),
                 }),
-                ..Default::default()
-            }),
-            labels: Some(hash_map! {
-                String::from("traefik.enable") => String::from("true"),
-                format!("traefik.http.routers.{}.rule", application.name) => format!("Host(`{}.{}`)",  application.configuration.as_ref().and_then(|configuration| configuration.domain.clone()).unwrap_or(application.name.clone()), self.routing_config.domain),
-                String::from("traefik.http.services.cleverclown.loadbalancer.server.port") => format!("{}", exposed_port),
-                String::from("cleverclown.domain") => application.configuration.as_ref().and_then(|configuration| configuration.domain.clone()).unwrap_or(application.name.clone()),
-                String::from("cleverclown.application.name") => application.name.clone()
+                binds: docker_binds(application),
+                ..docker_resources(&environment.resources)
             }),
+            labels: Some(
+                labels
+                    .into_iter()
+                    .chain(hash_map! {
+                        String::from("cleverclown.domain") => application.configuration.as_ref().and_then(|configuration| configuration.domain.clone()).unwrap_or(application.name.clone()),
+                        String::from("cleverclown.application.name") => application.name.clone(),
+                        String::from("cleverclown.process") => process.name.clone(),
+                        // Docker can't update a container, a change of its spec replaces it
+                        String::from("cleverclown.checksum") => application.instance_checksum(process, environment)
+                    })
+                    .collect(),
+            ),
             networking_config: Some(NetworkingConfig {
                 endpoints_config: hash_map! {
                     self.docker_config.network.clone() => EndpointSettings {
@@ -206,8 +379,9 @@ impl ContainerExecutor for DockerContainerExecutor {
             .create_container(
                 Some(CreateContainerOptions {
                     name: format!(
-                        "{}.{}",
+                        "{}.{}.{}",
                         application.name,
+                        process.name,
                         rand::thread_rng()
                             .sample_iter(&Alphanumeric)
                             .take(7)
@@ -225,11 +399,16 @@ impl ContainerExecutor for DockerContainerExecutor {
 
         Ok(Container {
             id: container.id,
-            image_id: image_id,
+            process: process.name.clone(),
+            image_id,
             started_at: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("Time went backward")
                 .as_secs(),
+            state: ContainerState::Running,
+            restart_count: 0,
+            health: application.healthcheck(process).map(|_| Health::Starting),
+            checksum: Some(application.instance_checksum(process, environment)),
         })
     }
 
@@ -247,6 +426,200 @@ impl ContainerExecutor for DockerContainerExecutor {
             .context(format!("Error while removing container {}", container.id))
     }
 
+    async fn instance_health(&self, _application_name: String, container: &Container) -> Result<Health, Error> {
+        let state = self
+            .docker
+            .inspect_container(container.id.as_str(), None)
+            .await
+            .context(format!("Error while inspecting container {}", container.id))?
+            .state
+            .unwrap_or_default();
+        // A container crashing on boot is restarted by its restart policy, never wait for it
+        if !state.running.unwrap_or(false) || state.restarting.unwrap_or(false) {
+            return Ok(Health::Unhealthy);
+        }
+        Ok(match state.health.and_then(|health| health.status) {
+            Some(HealthStatusEnum::STARTING) => Health::Starting,
+            Some(HealthStatusEnum::UNHEALTHY) => Health::Unhealthy,
+            _ => Health::Healthy,
+        })
+    }
+
+    async fn logs(&self, _application_name: String, containers: Vec<Container>, options: &LogOptions) -> Result<BoxStream<'static, Result<LogLine, Error>>, Error> {
+        let streams = containers.into_iter().map(|container| {
+            let instance = container.id.chars().take(12).collect::<String>();
+            self.docker
+                .logs(
+                    container.id.as_str(),
+                    Some(LogsOptions::<String> {
+                        follow: options.follow,
+                        stdout: true,
+                        stderr: true,
+                        since: options.since.and_then(|since| i64::try_from(since).ok()).unwrap_or(0),
+                        tail: options.tail.map(|tail| tail.to_string()).unwrap_or("all".to_string()),
+                        ..Default::default()
+                    }),
+                )
+                .map(move |output| match output {
+                    // A chunk holds what the process wrote at once, it can span several lines
+                    Ok(output) => output
+                        .to_string()
+                        .lines()
+                        .map(|message| Ok(LogLine { instance: instance.clone(), message: message.to_string() }))
+                        .collect::<Vec<_>>(),
+                    Err(e) => vec![Err(Error::from(e).context(format!("Error while reading logs of container {}", instance)))],
+                })
+                .flat_map(futures::stream::iter)
+                .boxed()
+        });
+        Ok(futures::stream::select_all(streams).boxed())
+    }
+
+    async fn run(&self, application: &Application, image_id: String, environment: &Environment, command: Vec<String>, timeout: Option<Duration>) -> Result<BoxStream<'static, Result<RunOutput, Error>>, Error> {
+        let config = Config {
+            image: Some(image_id),
+            cmd: Some(command),
+            env: Some(
+                environment
+                    .variables
+                    .iter()
+                    .chain(environment.secrets.iter())
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect(),
+            ),
+            host_config: Some(HostConfig {
+                binds: docker_binds(application),
+                ..docker_resources(&environment.resources)
+            }),
+            // Not labelled as an application instance, so it is never converged
+            labels: Some(hash_map! {
+                String::from("cleverclown.run") => application.name.clone()
+            }),
+            networking_config: Some(NetworkingConfig {
+                endpoints_config: hash_map! {
+                    self.docker_config.network.clone() => EndpointSettings {
+                        ..Default::default()
+                    }
+                },
+            }),
+            ..Default::default()
+        };
+        let container = self
+            .docker
+            .create_container(
+                Some(CreateContainerOptions {
+                    name: format!(
+                        "{}.run.{}",
+                        application.name,
+                        rand::thread_rng()
+                            .sample_iter(&Alphanumeric)
+                            .take(7)
+                            .map(char::from)
+                            .collect::<String>()
+                    ),
+                    ..Default::default()
+                }),
+                config,
+            )
+            .await
+            .context("Error while creating one-off container")?;
+        let docker = self.docker.clone();
+        if let Err(e) = docker.start_container(container.id.as_str(), None::<StartContainerOptions<String>>).await {
+            let _ = docker.remove_container(container.id.as_str(), Some(RemoveContainerOptions { force: true, ..Default::default() })).await;
+            return Err(Error::new(e).context("Error while starting one-off container"));
+        }
+        // The container is followed in its own task, so it is removed even if the output isn't read until the end
+        let (sender, receiver) = mpsc::channel(1024);
+        tokio::spawn(async move {
+            let id = container.id.as_str();
+            let output = async {
+                let mut logs = docker.logs(
+                    id,
+                    Some(LogsOptions::<String> {
+                        follow: true,
+                        stdout: true,
+                        stderr: true,
+                        ..Default::default()
+                    }),
+                );
+                while let Some(chunk) = logs.next().await {
+                    let chunk = chunk.context("Error while reading one-off container output")?;
+                    for line in chunk.to_string().lines() {
+                        let _ = sender.send(Ok(RunOutput::Line(line.to_string()))).await;
+                    }
+                }
+                match docker.wait_container(id, None::<WaitContainerOptions<String>>).next().await {
+                    Some(Ok(response)) => Ok(RunOutput::Exited(response.status_code)),
+                    Some(Err(bollard::errors::Error::DockerContainerWaitError { code, .. })) => Ok(RunOutput::Exited(code)),
+                    Some(Err(e)) => Err(Error::new(e).context("Error while waiting one-off container")),
+                    None => Err(anyhow!("One-off container {} exit code not found", id)),
+                }
+            };
+            let end = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, output).await.unwrap_or(Ok(RunOutput::TimedOut)),
+                None => output.await,
+            };
+            if let Err(e) = docker.remove_container(id, Some(RemoveContainerOptions { force: true, ..Default::default() })).await {
+                warn!("One-off container {} can't be removed: {:?}", id, e);
+            }
+            let _ = sender.send(end).await;
+        });
+        Ok(futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|output| (output, receiver))
+        })
+        .boxed())
+    }
+
+    async fn exec(&self, _application_name: String, container: &Container, options: &ExecOptions) -> Result<ExecSession, Error> {
+        let exec = self
+            .docker
+            .create_exec(
+                container.id.as_str(),
+                CreateExecOptions {
+                    cmd: Some(options.command()),
+                    attach_stdin: Some(options.stdin),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    tty: Some(options.tty),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context(format!("Error while creating exec in container {}", container.id))?;
+        let StartExecResults::Attached { output, input } = self
+            .docker
+            .start_exec(&exec.id, Some(StartExecOptions { detach: false, tty: options.tty, ..Default::default() }))
+            .await
+            .context(format!("Error while starting exec in container {}", container.id))?
+        else {
+            return Err(anyhow!("Exec in container {} started detached", container.id));
+        };
+        let resize = options.tty.then(|| {
+            let (sender, mut receiver) = mpsc::channel::<TerminalSize>(8);
+            let docker = self.docker.clone();
+            let exec_id = exec.id.clone();
+            // Ends with the session, once its sender is dropped
+            tokio::spawn(async move {
+                while let Some(size) = receiver.recv().await {
+                    if let Err(e) = docker.resize_exec(&exec_id, ResizeExecOptions { width: size.width, height: size.height }).await {
+                        warn!("Can't resize exec {} terminal {:?}", exec_id, e);
+                    }
+                }
+            });
+            sender
+        });
+        let docker = self.docker.clone();
+        let exec_id = exec.id;
+        Ok(ExecSession {
+            output: output
+                .map(|output| output.map(|output| output.into_bytes().to_vec()).context("Error while reading exec output"))
+                .boxed(),
+            input: options.stdin.then_some(input),
+            resize,
+            exit_code: async move { Ok(docker.inspect_exec(&exec_id).await?.exit_code) }.boxed(),
+        })
+    }
+
     async fn list_applications(&self) -> Result<Vec<String>, Error> {
         let containers = self.docker.list_containers::<String>(None).await?;
 
@@ -275,59 +648,43 @@ impl ContainerExecutor for DockerContainerExecutor {
             }).await?;
         }
         
-        let traefik_container_name = "cleverclown_traefik";
-        let container = match self.docker.inspect_container(traefik_container_name, None).await { //TODO should unwrap_or but future on op
+        let traefik_config = self.traefik_config();
+        // A traefik container created with another configuration is replaced
+        let checksum = config_checksum(&traefik_config);
+        let traefik_config = Config {
+            labels: Some(hash_map! { "cleverclown.routing.checksum".to_string() => checksum.clone() }),
+            ..traefik_config
+        };
+        let existing = match self.docker.inspect_container(TRAEFIK_CONTAINER, None).await { //TODO should unwrap_or but future on op
             Ok(traefik_container) => {
                 info!("Traefik http routing continer detected {}", traefik_container.id.clone().unwrap());
-                traefik_container
+                let up_to_date = traefik_container.config.as_ref()
+                    .and_then(|config| config.labels.as_ref())
+                    .and_then(|labels| labels.get("cleverclown.routing.checksum"))
+                    .is_some_and(|existing_checksum| *existing_checksum == checksum);
+                if up_to_date {
+                    Some(traefik_container)
+                } else {
+                    info!("Traefik container configuration is outdated, replacing it");
+                    self.docker.remove_container(TRAEFIK_CONTAINER, Some(RemoveContainerOptions { force: true, ..Default::default() })).await
+                        .context("Error while removing outdated traefik container")?;
+                    None
+                }
             }, 
-            Err(_) => { // TODO should check if error is just not existing
+            Err(_) => None, // TODO should check if error is just not existing
+        };
+        let container = match existing {
+            Some(container) => container,
+            None => {
                 info!("No routing traefik container detected, starting it");
-                let mut exposed_ports = hash_map! {
-                    "80/tcp".to_string() => HashMap::new(),
-                };
-                let mut port_binding = hash_map! {
-                    "80/tcp".to_string() => Some(vec![PortBinding { host_port: Some("80".to_string()), host_ip: None }])
-                };
-                let mut environment = vec![
-                    format!("TRAEFIK_PROVIDERS_DOCKER_NETWORK={}", self.docker_config.network),
-                    format!("TRAEFIK_PROVIDERS_DOCKER_EXPOSEDBYDEFAULT={}", "false"),
-                    format!("TRAEFIK_LOG_LEVEL={}", "info"),
-                    format!("TRAEFIK_LOG_NOCOLOR={}", "true"),
-                    format!("TRAEFIK_PROVIDERS_DOCKER_ENDPOINT=unix://{}", self.docker_config.socket)
-                ];
-                if self.routing_config.dashboard {
-                    exposed_ports.insert("8080/tcp".to_string(), HashMap::new());
-                    port_binding.insert("8080/tcp".to_string(), Some(vec![PortBinding { host_port: Some("8080".to_string()), host_ip: None }]));
-                    environment.push("TRAEFIK_API_INSECURE=true".to_string());
-                }
-                let traefik_config = Config {
-                    image: Some("traefik:v3.1".to_string()),
-                    env: Some(environment),
-                    exposed_ports: Some(exposed_ports),
-                    host_config: Some(HostConfig {
-                        port_bindings: Some(port_binding),
-                        binds: Some(vec![format!("{}:{}", self.docker_config.socket, self.docker_config.socket)]),
-                        restart_policy: Some(RestartPolicy {
-                            name: Some(RestartPolicyNameEnum::ON_FAILURE),
-                            maximum_retry_count: Some(3),
-                        }),
-                        ..Default::default()
-                    }),
-                    networking_config: Some(NetworkingConfig { endpoints_config: hash_map! { 
-                        self.docker_config.network.clone() => EndpointSettings { ..Default::default() } 
-                    }}), 
-                    ..Default::default()
-                };
                 let container_name = self.docker.create_container(Some(CreateContainerOptions{
-                    name: traefik_container_name,
+                    name: TRAEFIK_CONTAINER,
                     platform: None,
                 }), traefik_config).await?;
                 info!("Created container {}", container_name.id);
-                self.docker.inspect_container(&container_name.id.as_str(), None).await.context("Error while inspecting newly created traefik container")?
+                self.docker.inspect_container(container_name.id.as_str(), None).await.context("Error while inspecting newly created traefik container")?
             }
         };
-        // TODO should check config is up to date
         if !container.state.and_then(|state| state.running).unwrap_or(false) {
             info!("Starting traefik container");
             self.docker.start_container::<String>(container.id.unwrap().as_str(), None).await.context("Error starting traefik container for routing")
@@ -338,17 +695,283 @@ impl ContainerExecutor for DockerContainerExecutor {
 
     }
 
+    fn scales_processes(&self) -> bool {
+        false
+    }
+
+    async fn process_load(&self, application: &Application, process: &Process, containers: &[Container]) -> Result<Option<ProcessLoad>, Error> {
+        let Some(ref autoscaling) = process.autoscaling else {
+            return Ok(None);
+        };
+        let cpu = match autoscaling.target_cpu {
+            Some(_) if !containers.is_empty() => {
+                let usages = futures::future::try_join_all(containers.iter().map(|container| self.cpu_usage(container.id.as_str()))).await?;
+                Some(u32::try_from(usages.iter().sum::<u64>() / usages.len() as u64).unwrap_or(u32::MAX))
+            }
+            _ => None,
+        };
+        let requests = match autoscaling.target_requests {
+            Some(_) if process.routed() => {
+                let total = service_requests(self.traefik_metrics().await?.as_str(), Route::new(application, &self.routing_config).service.as_str());
+                let now = Instant::now();
+                let previous = self.request_samples.lock().expect("Request samples poisoned").insert(application.name.clone(), (total, now));
+                // No rate on the first read nor after a reset of the traefik counters
+                previous
+                    .filter(|(count, _)| *count <= total)
+                    .map(|(count, at)| (total - count) / now.duration_since(at).as_secs_f64())
+            }
+            _ => None,
+        };
+        Ok(Some(ProcessLoad { cpu, requests }))
+    }
+
+    async fn create_addon(&self, name: String, kind: AddonKind, password: String) -> Result<String, Error> {
+        let container_name = docker_addon_name(name.as_str());
+        let host = format!("addon--{}", name);
+        self.docker
+            .create_image(
+                Some(CreateImageOptions {
+                    from_image: kind.image(),
+                    ..Default::default()
+                }),
+                None,
+                None,
+            )
+            .try_collect::<Vec<CreateImageInfo>>()
+            .await
+            .context("Error while pulling add-on image")?;
+        self.docker
+            .create_volume(CreateVolumeOptions {
+                name: container_name.clone(),
+                labels: hash_map! { String::from("cleverclown.addon") => name.clone() },
+                ..Default::default()
+            })
+            .await
+            .context("Error while creating add-on volume")?;
+        let (env, cmd) = match kind {
+            AddonKind::Postgres => (vec![format!("POSTGRES_PASSWORD={}", password)], None),
+            AddonKind::Redis => (
+                vec![],
+                Some(vec![
+                    String::from("redis-server"),
+                    String::from("--requirepass"),
+                    password,
+                    String::from("--appendonly"),
+                    String::from("yes"),
+                ]),
+            ),
+        };
+        let config = Config {
+            image: Some(kind.image().to_string()),
+            env: Some(env),
+            cmd,
+            host_config: Some(HostConfig {
+                binds: Some(vec![format!("{}:{}", container_name, kind.data_path())]),
+                restart_policy: Some(RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            // Not labelled as an application instance, so it is never converged
+            labels: Some(hash_map! { String::from("cleverclown.addon") => name.clone() }),
+            networking_config: Some(NetworkingConfig {
+                endpoints_config: hash_map! {
+                    self.docker_config.network.clone() => EndpointSettings {
+                        aliases: Some(vec![host.clone()]),
+                        ..Default::default()
+                    }
+                },
+            }),
+            ..Default::default()
+        };
+        let container = self
+            .docker
+            .create_container(
+                Some(CreateContainerOptions {
+                    name: container_name.clone(),
+                    ..Default::default()
+                }),
+                config,
+            )
+            .await
+            .context("Error while creating add-on container")?;
+        self.docker
+            .start_container(container.id.as_str(), None::<StartContainerOptions<String>>)
+            .await
+            .context("Error while starting add-on container")?;
+        Ok(host)
+    }
+
+    async fn delete_addon(&self, name: String) -> Result<(), Error> {
+        let container_name = docker_addon_name(name.as_str());
+        self.docker
+            .remove_container(container_name.as_str(), Some(RemoveContainerOptions { force: true, ..Default::default() }))
+            .await
+            .context("Error while removing add-on container")?;
+        self.docker
+            .remove_volume(container_name.as_str(), Some(RemoveVolumeOptions { force: true }))
+            .await
+            .context("Error while removing add-on volume")
+    }
+
 }
 
 impl DockerContainerExecutor {
+    /// Cpu usage of the container in millicores, between the two samples of a docker stats read
+    async fn cpu_usage(&self, container_id: &str) -> Result<u64, Error> {
+        let stats = self
+            .docker
+            .stats(container_id, Some(StatsOptions { stream: false, one_shot: false }))
+            .next()
+            .await
+            .ok_or(anyhow!("No stats read for container {}", container_id))?
+            .context(format!("Error while reading stats of container {}", container_id))?;
+        let usage = stats.cpu_stats.cpu_usage.total_usage.saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or(0)
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or(0));
+        let cpus = stats.cpu_stats.online_cpus.unwrap_or(1);
+        Ok((usage * cpus * 1000).checked_div(system).unwrap_or(0))
+    }
+
+    /// Prometheus metrics of traefik, read from inside its container as the platform may not be on the docker network
+    async fn traefik_metrics(&self) -> Result<String, Error> {
+        let exec = self
+            .docker
+            .create_exec(
+                TRAEFIK_CONTAINER,
+                CreateExecOptions {
+                    cmd: Some(vec!["wget", "-qO-", "http://127.0.0.1:8082/metrics"]),
+                    attach_stdout: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context("Error while reading traefik metrics")?;
+        let StartExecResults::Attached { output, .. } = self.docker.start_exec(&exec.id, None).await? else {
+            return Err(anyhow!("Traefik metrics read started detached"));
+        };
+        let metrics = output
+            .map_ok(|output| output.into_bytes())
+            .try_collect::<Vec<_>>()
+            .await
+            .context("Error while reading traefik metrics")?;
+        Ok(String::from_utf8_lossy(&metrics.concat()).into_owned())
+    }
+
+    fn traefik_config(&self) -> Config<String> {
+        let mut exposed_ports = hash_map! {
+            "80/tcp".to_string() => HashMap::new(),
+            "443/tcp".to_string() => HashMap::new(),
+        };
+        let mut port_binding = hash_map! {
+            "80/tcp".to_string() => Some(vec![PortBinding { host_port: Some("80".to_string()), host_ip: None }]),
+            "443/tcp".to_string() => Some(vec![PortBinding { host_port: Some("443".to_string()), host_ip: None }])
+        };
+        let tls_config = &self.routing_config.tls;
+        let mut environment = vec![
+            format!("TRAEFIK_PROVIDERS_DOCKER_NETWORK={}", self.docker_config.network),
+            format!("TRAEFIK_PROVIDERS_DOCKER_EXPOSEDBYDEFAULT={}", "false"),
+            format!("TRAEFIK_LOG_LEVEL={}", "info"),
+            format!("TRAEFIK_LOG_NOCOLOR={}", "true"),
+            format!("TRAEFIK_PROVIDERS_DOCKER_ENDPOINT=unix://{}", self.docker_config.socket),
+            "TRAEFIK_ENTRYPOINTS_WEB_ADDRESS=:80".to_string(),
+            "TRAEFIK_ENTRYPOINTS_WEBSECURE_ADDRESS=:443".to_string(),
+            // Requests per service are read by the autoscaling, the entrypoint isn't published
+            "TRAEFIK_ENTRYPOINTS_METRICS_ADDRESS=:8082".to_string(),
+            "TRAEFIK_METRICS_PROMETHEUS=true".to_string(),
+            "TRAEFIK_METRICS_PROMETHEUS_ENTRYPOINT=metrics".to_string(),
+            "TRAEFIK_METRICS_PROMETHEUS_ADDSERVICESLABELS=true".to_string(),
+            // User supplied certificates are written as dynamic configuration files
+            format!("TRAEFIK_PROVIDERS_FILE_DIRECTORY={}", TRAEFIK_DYNAMIC_DIRECTORY),
+            "TRAEFIK_PROVIDERS_FILE_WATCH=true".to_string(),
+            format!("TRAEFIK_CERTIFICATESRESOLVERS_{}_ACME_CASERVER={}", ACME_RESOLVER.to_uppercase(), tls_config.acme_server),
+            format!("TRAEFIK_CERTIFICATESRESOLVERS_{}_ACME_STORAGE=/acme/acme.json", ACME_RESOLVER.to_uppercase()),
+            format!("TRAEFIK_CERTIFICATESRESOLVERS_{}_ACME_HTTPCHALLENGE_ENTRYPOINT=web", ACME_RESOLVER.to_uppercase()),
+        ];
+        let mut binds = vec![
+            format!("{}:{}", self.docker_config.socket, self.docker_config.socket),
+            "cleverclown-traefik-acme:/acme".to_string(),
+            format!("cleverclown-traefik-dynamic:{}", TRAEFIK_DYNAMIC_DIRECTORY),
+        ];
+        if let Some(ref email) = tls_config.acme_email {
+            environment.push(format!("TRAEFIK_CERTIFICATESRESOLVERS_{}_ACME_EMAIL={}", ACME_RESOLVER.to_uppercase(), email));
+        }
+        if let Some(ref ca_certificate) = tls_config.acme_ca_certificate {
+            // Trusted by the ACME client of traefik, needed for a Pebble test server
+            binds.push(format!("{}:/etc/traefik/acme-ca.pem:ro", ca_certificate));
+            environment.push("LEGO_CA_CERTIFICATES=/etc/traefik/acme-ca.pem".to_string());
+        }
+        if self.routing_config.dashboard {
+            exposed_ports.insert("8080/tcp".to_string(), HashMap::new());
+            port_binding.insert("8080/tcp".to_string(), Some(vec![PortBinding { host_port: Some("8080".to_string()), host_ip: None }]));
+            environment.push("TRAEFIK_API_INSECURE=true".to_string());
+        }
+        Config {
+            image: Some("traefik:v3.1".to_string()),
+            env: Some(environment),
+            exposed_ports: Some(exposed_ports),
+            host_config: Some(HostConfig {
+                port_bindings: Some(port_binding),
+                binds: Some(binds),
+                restart_policy: Some(RestartPolicy {
+                    name: Some(RestartPolicyNameEnum::ON_FAILURE),
+                    maximum_retry_count: Some(3),
+                }),
+                ..Default::default()
+            }),
+            networking_config: Some(NetworkingConfig { endpoints_config: hash_map! { 
+                self.docker_config.network.clone() => EndpointSettings { ..Default::default() } 
+            }}), 
+            ..Default::default()
+        }
+    }
+
+    /// Write the user supplied certificate of the application for the traefik file provider, or remove a previous one
+    async fn sync_certificate(&self, router: &str, certificate: Option<String>) -> Result<(), Error> {
+        let file_name = format!("{}.yml", router);
+        match certificate {
+            Some(certificate_config) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(certificate_config.len() as u64);
+                header.set_mode(0o600);
+                header.set_cksum();
+                let mut tar = tar::Builder::new(Vec::new());
+                tar.append_data(&mut header, file_name.as_str(), certificate_config.as_bytes())?;
+                self.docker.upload_to_container(TRAEFIK_CONTAINER, Some(UploadToContainerOptions {
+                    path: TRAEFIK_DYNAMIC_DIRECTORY,
+                    ..Default::default()
+                }), tar.into_inner()?.into()).await.context("Error while writing certificate to traefik")
+            }
+            None => {
+                let path = format!("{}/{}", TRAEFIK_DYNAMIC_DIRECTORY, file_name);
+                let removed = async {
+                    let exec = self.docker.create_exec(TRAEFIK_CONTAINER, CreateExecOptions {
+                        cmd: Some(vec!["rm", "-f", path.as_str()]),
+                        ..Default::default()
+                    }).await?;
+                    self.docker.start_exec(&exec.id, Some(StartExecOptions { detach: true, ..Default::default() })).await
+                }.await;
+                // A leftover certificate is only served for the host it was issued for, not worth failing on
+                if let Err(e) = removed {
+                    warn!("Can't remove certificate {} from traefik {:?}", path, e);
+                }
+                Ok(())
+            }
+        }
+    }
+
     async fn extract_min_exposed_port(&self, image_id: &str) -> Result<u16, Error> {
         self.docker
             .inspect_image(image_id)
             .await?
             .config
             .and_then(|c| c.exposed_ports)
-            .and_then(|exposed_ports| exposed_ports.into_iter().map(|(port, _)| port).min())
-            .and_then(|port| port.split("/").next().map(|str| String::from(str)))
+            .and_then(|exposed_ports| exposed_ports.into_keys().min())
+            .and_then(|port| port.split("/").next().map(String::from))
             .ok_or(anyhow!("Can't detect exposed port for {} image. Please define it in the application configuration or add EXPOSE to image", image_id))
             .and_then(|port_as_string| port_as_string.parse::<u16>().context("Exposed port can't be parsed"))
     }
@@ -356,8 +979,9 @@ impl DockerContainerExecutor {
     async fn build_docker_image(
         &self,
         local_dir: String,
-        application_name: String,
+        image_name: String,
         dockerfile: String,
+        reporter: &dyn DeploymentReporter,
     ) -> Result<String, Error> {
         let tar_gz = BytesMut::new().writer();
         let enc = GzEncoder::new(tar_gz, Compression::default());
@@ -366,55 +990,70 @@ impl DockerContainerExecutor {
 
         let tar_gz = tar.into_inner()?.finish()?;
 
-        info!("Build image {}", application_name.as_str());
-        self.docker
-            .build_image(
-                BuildImageOptions {
-                    dockerfile: dockerfile.as_str(),
-                    t: application_name.as_str(),
-                    version: bollard::image::BuilderVersion::BuilderBuildKit,
-                    pull: true,
-                    session: Some("buildx-session".into()),
-                    ..Default::default()
-                },
-                None,
-                Some(tar_gz.into_inner().freeze()),
-            )
-            .fuse()
-            .filter_map(|info| match info.map(|x| x.aux) {
-                Ok(Some(BuildInfoAux::BuildKit(response))) => {
+        reporter.log(format!("Build image {}", image_name.as_str())).await;
+        let mut build = self.docker.build_image(
+            BuildImageOptions {
+                dockerfile: dockerfile.as_str(),
+                t: image_name.as_str(),
+                version: bollard::image::BuilderVersion::BuilderBuildKit,
+                pull: true,
+                session: Some("buildx-session".into()),
+                ..Default::default()
+            },
+            None,
+            Some(tar_gz.into_inner().freeze()),
+        );
+        let mut image_id = None;
+        while let Some(info) = build.next().await {
+            let info = info.context("Error while building image")?;
+            if let Some(stream) = info.stream {
+                for line in stream.lines() {
+                    reporter.log(format!("Build => {}", line)).await;
+                }
+            }
+            if let Some(error) = info.error {
+                return Err(anyhow!("Image build failed: {}", error));
+            }
+            match info.aux {
+                Some(BuildInfoAux::BuildKit(response)) => {
                     for vertex in response.vertexes {
-                        if vertex.completed.is_some() {
-                            info!("Buildx => [Vertex] {}", vertex.name)
+                        if !vertex.error.is_empty() {
+                            reporter.log(format!("Buildx => [Error] {} {}", vertex.name, vertex.error)).await
+                        } else if vertex.completed.is_some() {
+                            reporter.log(format!("Buildx => [Vertex] {}", vertex.name)).await
                         }
                     }
                     for status in response.statuses {
                         if status.completed.is_some() {
-                            info!("Buildx => [Status] {}", status.id)
+                            reporter.log(format!("Buildx => [Status] {}", status.id)).await
+                        }
+                    }
+                    for log in response.logs {
+                        for line in String::from_utf8_lossy(&log.msg).lines() {
+                            reporter.log(format!("Buildx => {}", line)).await
                         }
                     }
-                    std::future::ready(None)
                 }
-                Ok(Some(BuildInfoAux::Default(image_id))) => std::future::ready(Some(image_id.id)),
-                _ => std::future::ready(None),
-            })
-            .select_next_some()
-            .await
-            .ok_or(anyhow!("Image built but cannot detect image id"))
+                Some(BuildInfoAux::Default(built_image)) => image_id = image_id.or(built_image.id),
+                None => {}
+            }
+        }
+        image_id.ok_or(anyhow!("Image built but cannot detect image id"))
     }
 
     async fn build_image_buildpack(
         &self,
         local_dir: String,
-        application_name: String,
+        image_name: String,
+        reporter: &dyn DeploymentReporter,
     ) -> Result<String, Error> {
         let buildpack_config = Config {
             image: Some("buildpacksio/pack"),
             cmd: Some(vec![
                 "build",
-                application_name.as_str(),
+                image_name.as_str(),
                 "--builder",
-                "heroku/builder:24",
+                BUILDPACK_BUILDER,
             ]),
             working_dir: Some("/workspace"),
             host_config: Some(HostConfig {
@@ -451,6 +1090,7 @@ impl DockerContainerExecutor {
             .start_container::<String>(&buildpack_container_id, None)
             .await?;
 
+        reporter.log(format!("Build image {} with buildpacks", image_name.as_str())).await;
         let AttachContainerResults { mut output, .. } = self
             .docker
             .attach_container(
@@ -459,18 +1099,150 @@ impl DockerContainerExecutor {
                     stdout: Some(true),
                     stderr: Some(true),
                     stream: Some(true),
+                    // Replay what was written before attaching
+                    logs: Some(true),
                     ..Default::default()
                 }),
             )
             .await?;
         while let Some(Ok(output)) = output.next().await {
             match output {
-                LogOutput::StdOut { message } => info!("Buildpack => {:?}", message),
-                LogOutput::StdErr { message } => warn!("Buildpack => {:?}", message),
+                LogOutput::StdOut { message } | LogOutput::StdErr { message } => {
+                    for line in String::from_utf8_lossy(&message).lines() {
+                        reporter.log(format!("Buildpack => {}", line)).await
+                    }
+                }
                 _ => {}
             }
         }
+        let exit = self
+            .docker
+            .wait_container(&buildpack_container_id, None::<WaitContainerOptions<String>>)
+            .try_collect::<Vec<_>>()
+            .await;
         self.docker.remove_container(buildpack_container_id.as_str(), None).await?;
-        Ok(application_name)
+        match exit {
+            Err(bollard::errors::Error::DockerContainerWaitError { code, .. }) => {
+                Err(anyhow!("Buildpack build failed with exit code {}", code))
+            }
+            Err(e) => Err(Error::new(e).context("Error while waiting buildpack build")),
+            // Resolve the tag to the built image id, so a release keeps pointing to this build
+            Ok(_) => self
+                .docker
+                .inspect_image(image_name.as_str())
+                .await
+                .context("Can't detect image built by buildpack")
+                .and_then(|docker_image| {
+                    docker_image
+                        .id
+                        .ok_or(anyhow!("Can't detect id of image built by buildpack"))
+                }),
+        }
+    }
+}
+
+/// Checksum of the settings of a container configuration, stable across maps ordering
+fn config_checksum(config: &Config<String>) -> String {
+    let mut settings = config.env.iter().flatten().cloned()
+        .chain(config.image.clone())
+        .chain(config.exposed_ports.iter().flat_map(|ports| ports.keys().cloned()))
+        .chain(config.host_config.iter().flat_map(|host_config| host_config.binds.iter().flatten().cloned()))
+        .collect::<Vec<_>>();
+    settings.sort();
+    let mut hasher = DefaultHasher::new();
+    settings.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+/// Directory and Dockerfile, or buildpack builder, the image of a commit is built with
+fn build_checksum(directory: Option<&str>, dockerfile: Option<&str>) -> String {
+    let build = match dockerfile {
+        Some(dockerfile) => serde_json::json!({ "directory": directory, "dockerfile": dockerfile }),
+        None => serde_json::json!({ "directory": directory, "builder": BUILDPACK_BUILDER }),
+    };
+    // Truncated to keep the tag short, it only tells builds of a commit apart
+    checksum(build.to_string())[..16].to_string()
+}
+
+/// Total requests handled by the traefik service of the docker provider
+fn service_requests(metrics: &str, service: &str) -> f64 {
+    let label = format!("service=\"{}@docker\"", service);
+    metrics
+        .lines()
+        .filter(|line| line.starts_with("traefik_service_requests_total{") && line.contains(label.as_str()))
+        .filter_map(|line| line.rsplit_once(' '))
+        .filter_map(|(_, value)| value.parse::<f64>().ok())
+        .sum()
+}
+
+/// Name of both the container and the volume of the add-on
+fn docker_addon_name(name: &str) -> String {
+    format!("cleverclown_addon_{}", name)
+}
+
+/// Volumes are prefixed as docker ones are shared by every container of the host
+fn docker_volume_name(application: &str, volume: &str) -> String {
+    format!("cleverclown-{}--{}", application, volume)
+}
+
+fn docker_binds(application: &Application) -> Option<Vec<String>> {
+    let volumes = application.volumes();
+    (!volumes.is_empty()).then(|| {
+        volumes
+            .iter()
+            .map(|volume| format!("{}:{}", docker_volume_name(application.name.as_str(), volume.name.as_str()), volume.mount_path))
+            .collect()
+    })
+}
+
+/// Limits and reservations of a container, docker has no cpu reservation so the request sets its relative weight
+fn docker_resources(resources: &Resources) -> HostConfig {
+    const MIB: i64 = 1024 * 1024;
+    HostConfig {
+        nano_cpus: resources.cpu_limit.map(|millicores| i64::from(millicores) * 1_000_000),
+        memory: resources.memory_limit.map(|mib| i64::from(mib) * MIB),
+        cpu_shares: resources.cpu_request.map(|millicores| i64::from(millicores) * 1024 / 1000),
+        memory_reservation: resources.memory_request.map(|mib| i64::from(mib) * MIB),
+        ..Default::default()
     }
 }
+
+/// Docker HEALTHCHECK running inside the container, http and tcp probes need curl/wget or nc/bash in the image
+fn docker_healthcheck(healthcheck: &HealthCheck, exposed_port: Option<u16>) -> Result<HealthConfig, Error> {
+    let probe_port = |port: Option<u16>| {
+        port.or(exposed_port)
+            .ok_or(anyhow!("Health probe needs a port when the application doesn't expose one"))
+    };
+    let test = match healthcheck.probe {
+        HealthProbe::Http { ref path, port } => {
+            let url = format!("http://localhost:{}{}", probe_port(port)?, path);
+            vec![
+                "CMD-SHELL".to_string(),
+                format!("curl -fs -o /dev/null {url} || wget -q -O /dev/null {url} || exit 1"),
+            ]
+        }
+        HealthProbe::Tcp { port } => {
+            let port = probe_port(port)?;
+            vec![
+                "CMD-SHELL".to_string(),
+                format!("nc -z localhost {port} || bash -c '</dev/tcp/localhost/{port}' || exit 1"),
+            ]
+        }
+        HealthProbe::Command { ref command } => {
+            let mut test = vec!["CMD".to_string()];
+            test.extend(command.iter().cloned());
+            test
+        }
+    };
+    Ok(HealthConfig {
+        test: Some(test),
+        interval: Some(nanoseconds(healthcheck.interval())),
+        timeout: Some(nanoseconds(healthcheck.timeout())),
+        retries: Some(i64::from(healthcheck.retries())),
+        ..Default::default()
+    })
+}
+
+fn nanoseconds(seconds: u64) -> i64 {
+    i64::try_from(seconds.saturating_mul(1_000_000_000)).unwrap_or(i64::MAX)
+}
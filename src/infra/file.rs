@@ -0,0 +1,317 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::domain::{
+    model::{Addon, Application, Deployment, GitCredential, RegistryCredential, Release, Secret},
+    port::{
+        AddonRepository, ApplicationRepository, DeploymentRepository, GitCredentialRepository,
+        RegistryCredentialRepository, ReleaseRepository, SecretRepository,
+    },
+};
+
+/// Json on disk storage, one file per stored entity
+#[derive(Clone)]
+pub struct FileRepository {
+    pub directory: PathBuf,
+}
+
+#[async_trait]
+impl ApplicationRepository for FileRepository {
+    async fn save(&self, application: &Application) -> Result<(), Error> {
+        write_json(
+            &self.entity_path("applications", &application.name)?,
+            application,
+        )
+        .await
+    }
+
+    async fn get(&self, application_name: String) -> Result<Option<Application>, Error> {
+        read_json(&self.entity_path("applications", &application_name)?).await
+    }
+
+    async fn list(&self) -> Result<Vec<Application>, Error> {
+        list_json(&self.directory.join("applications")).await
+    }
+
+    async fn delete(&self, application_name: String) -> Result<(), Error> {
+        remove_file(&self.entity_path("images", &application_name)?).await?;
+        remove_file(&self.entity_path("applications", &application_name)?).await
+    }
+
+    async fn save_image(&self, application_name: String, image_id: String) -> Result<(), Error> {
+        write_json(&self.entity_path("images", &application_name)?, &image_id).await
+    }
+
+    async fn image(&self, application_name: String) -> Result<Option<String>, Error> {
+        read_json(&self.entity_path("images", &application_name)?).await
+    }
+}
+
+#[async_trait]
+impl DeploymentRepository for FileRepository {
+    async fn save(&self, deployment: &Deployment) -> Result<(), Error> {
+        write_json(
+            &self.entity_path("deployments", &deployment.id)?,
+            deployment,
+        )
+        .await
+    }
+
+    async fn get(&self, deployment_id: String) -> Result<Option<Deployment>, Error> {
+        read_json(&self.entity_path("deployments", &deployment_id)?).await
+    }
+
+    async fn append_log(&self, deployment_id: String, line: String) -> Result<(), Error> {
+        let path = self
+            .entity_path("deployments", &deployment_id)?
+            .with_extension("log");
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(&path)
+            .await
+            .context(format!("Can't open {}", path.display()))?;
+        file.write_all(format!("{}\n", line).as_bytes())
+            .await
+            .context(format!("Can't write {}", path.display()))
+    }
+
+    async fn logs(&self, deployment_id: String) -> Result<Vec<String>, Error> {
+        let path = self
+            .entity_path("deployments", &deployment_id)?
+            .with_extension("log");
+        match fs::read_to_string(&path).await {
+            Ok(content) => Ok(content.lines().map(String::from).collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(Error::new(e).context(format!("Can't read {}", path.display()))),
+        }
+    }
+}
+
+#[async_trait]
+impl ReleaseRepository for FileRepository {
+    async fn save(&self, release: &Release) -> Result<(), Error> {
+        let kind = format!("releases/{}", check_name(&release.application.name)?);
+        write_json(
+            &self.entity_path(&kind, &release.version.to_string())?,
+            release,
+        )
+        .await
+    }
+
+    async fn list(&self, application_name: String) -> Result<Vec<Release>, Error> {
+        let directory = self
+            .directory
+            .join("releases")
+            .join(check_name(&application_name)?);
+        let mut releases: Vec<Release> = list_json(&directory).await?;
+        releases.sort_by_key(|release| release.version);
+        Ok(releases)
+    }
+
+    async fn delete(&self, application_name: String) -> Result<(), Error> {
+        let directory = self
+            .directory
+            .join("releases")
+            .join(check_name(&application_name)?);
+        match fs::remove_dir_all(&directory).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::new(e).context(format!("Can't remove {}", directory.display()))),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretRepository for FileRepository {
+    async fn save(&self, secret: &Secret) -> Result<(), Error> {
+        write_json(&self.entity_path("secrets", &secret.name)?, secret).await
+    }
+
+    async fn get(&self, secret_name: String) -> Result<Option<Secret>, Error> {
+        read_json(&self.entity_path("secrets", &secret_name)?).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        Ok(list_json::<Secret>(&self.directory.join("secrets"))
+            .await?
+            .into_iter()
+            .map(|secret| secret.name)
+            .collect())
+    }
+
+    async fn delete(&self, secret_name: String) -> Result<(), Error> {
+        remove_file(&self.entity_path("secrets", &secret_name)?).await
+    }
+}
+
+#[async_trait]
+impl GitCredentialRepository for FileRepository {
+    async fn save(&self, credential: &GitCredential) -> Result<(), Error> {
+        write_json(
+            &self.entity_path("git-credentials", &credential.name)?,
+            credential,
+        )
+        .await
+    }
+
+    async fn get(&self, credential_name: String) -> Result<Option<GitCredential>, Error> {
+        read_json(&self.entity_path("git-credentials", &credential_name)?).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        Ok(
+            list_json::<GitCredential>(&self.directory.join("git-credentials"))
+                .await?
+                .into_iter()
+                .map(|credential| credential.name)
+                .collect(),
+        )
+    }
+
+    async fn delete(&self, credential_name: String) -> Result<(), Error> {
+        remove_file(&self.entity_path("git-credentials", &credential_name)?).await
+    }
+}
+
+#[async_trait]
+impl RegistryCredentialRepository for FileRepository {
+    async fn save(&self, credential: &RegistryCredential) -> Result<(), Error> {
+        write_json(
+            &self.entity_path("registry-credentials", &credential.registry)?,
+            credential,
+        )
+        .await
+    }
+
+    async fn get(&self, registry: String) -> Result<Option<RegistryCredential>, Error> {
+        read_json(&self.entity_path("registry-credentials", &registry)?).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        Ok(
+            list_json::<RegistryCredential>(&self.directory.join("registry-credentials"))
+                .await?
+                .into_iter()
+                .map(|credential| credential.registry)
+                .collect(),
+        )
+    }
+
+    async fn delete(&self, registry: String) -> Result<(), Error> {
+        remove_file(&self.entity_path("registry-credentials", &registry)?).await
+    }
+}
+
+#[async_trait]
+impl AddonRepository for FileRepository {
+    async fn save(&self, addon: &Addon) -> Result<(), Error> {
+        write_json(&self.entity_path("addons", &addon.name)?, addon).await
+    }
+
+    async fn get(&self, addon_name: String) -> Result<Option<Addon>, Error> {
+        read_json(&self.entity_path("addons", &addon_name)?).await
+    }
+
+    async fn list(&self) -> Result<Vec<String>, Error> {
+        Ok(list_json::<Addon>(&self.directory.join("addons"))
+            .await?
+            .into_iter()
+            .map(|addon| addon.name)
+            .collect())
+    }
+
+    async fn delete(&self, addon_name: String) -> Result<(), Error> {
+        remove_file(&self.entity_path("addons", &addon_name)?).await
+    }
+}
+
+impl FileRepository {
+    fn entity_path(&self, kind: &str, name: &str) -> Result<PathBuf, Error> {
+        Ok(self
+            .directory
+            .join(kind)
+            .join(format!("{}.json", check_name(name)?)))
+    }
+}
+
+/// Names are used as file names, they must not escape the storage directory
+fn check_name(name: &str) -> Result<&str, Error> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(anyhow!("Invalid name {} for storage", name));
+    }
+    Ok(name)
+}
+
+async fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await.context(format!(
+            "Can't create storage directory {}",
+            parent.display()
+        ))?;
+    }
+    // Write then rename so a crash never leaves a truncated file behind
+    let temporary = path.with_extension("json.tmp");
+    // Stored entities can hold secret values, only the server user can read them
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temporary)
+        .await
+        .context(format!("Can't write {}", temporary.display()))?;
+    file.write_all(&serde_json::to_vec_pretty(value)?)
+        .await
+        .context(format!("Can't write {}", temporary.display()))?;
+    fs::rename(&temporary, path)
+        .await
+        .context(format!("Can't write {}", path.display()))
+}
+
+async fn read_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, Error> {
+    match fs::read(path).await {
+        Ok(content) => serde_json::from_slice(&content)
+            .map(Some)
+            .context(format!("Can't deserialize {}", path.display())),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::new(e).context(format!("Can't read {}", path.display()))),
+    }
+}
+
+async fn list_json<T: DeserializeOwned>(directory: &Path) -> Result<Vec<T>, Error> {
+    let mut entries = match fs::read_dir(directory).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::new(e).context(format!("Can't list {}", directory.display()))),
+    };
+    let mut values = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            if let Some(value) = read_json(&path).await? {
+                values.push(value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+async fn remove_file(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(Error::new(e).context(format!("Can't remove {}", path.display()))),
+    }
+}
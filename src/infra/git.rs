@@ -0,0 +1,213 @@
+use std::{
+    cell::Cell,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{anyhow, Error};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    CertificateCheckStatus, Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions, Object,
+    RemoteCallbacks, Repository,
+};
+
+use crate::domain::model::{GitAuth, GitCredential};
+
+/// Clone the remote into `local_dir` and check out the reference, default branch when unset.
+/// Return the commit SHA of the checked out sources
+pub fn clone(
+    remote: &str,
+    reference: Option<&str>,
+    local_dir: &Path,
+    credential: Option<&GitCredential>,
+    known_hosts: &Path,
+) -> Result<String, Error> {
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks(credential, known_hosts));
+    let repository = RepoBuilder::new()
+        .fetch_options(fetch_options)
+        .clone(remote, local_dir)
+        .map_err(|e| match e.code() {
+            ErrorCode::Auth => match credential {
+                Some(credential) => anyhow!(
+                    "Authentication to git repository {} failed with credential {}: {}",
+                    remote,
+                    credential.name,
+                    e.message()
+                ),
+                None => anyhow!(
+                    "Git repository {} requires authentication, reference a git credential in the source: {}",
+                    remote,
+                    e.message()
+                ),
+            },
+            ErrorCode::Certificate => anyhow!(
+                "Host key verification of git repository {} failed: {}",
+                remote,
+                e.message()
+            ),
+            _ => Error::from(e).context(format!("Error while cloning git repository {}", remote)),
+        })?;
+    let commit = match reference {
+        Some(reference) => {
+            let commit = resolve(&repository, reference)?.peel_to_commit()?;
+            repository.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))?;
+            repository.set_head_detached(commit.id())?;
+            commit
+        }
+        None => repository.head()?.peel_to_commit()?,
+    };
+    Ok(commit.id().to_string())
+}
+
+fn callbacks<'a>(
+    credential: Option<&'a GitCredential>,
+    known_hosts: &'a Path,
+) -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    // libgit2 asks again for credentials as long as they are rejected
+    let attempted = Cell::new(false);
+    callbacks.credentials(move |_url, username_from_url, allowed| {
+        let Some(credential) = credential else {
+            return Err(git2::Error::new(
+                ErrorCode::Auth,
+                ErrorClass::Callback,
+                "no git credential configured",
+            ));
+        };
+        // An ssh url without user first asks for the username alone, it isn't an attempt
+        if let GitAuth::Ssh { ref username, .. } = credential.auth {
+            if allowed.contains(CredentialType::USERNAME) {
+                return Cred::username(username.as_deref().or(username_from_url).unwrap_or("git"));
+            }
+        }
+        if attempted.replace(true) {
+            return Err(git2::Error::new(
+                ErrorCode::Auth,
+                ErrorClass::Callback,
+                "credential rejected by the remote",
+            ));
+        }
+        match credential.auth {
+            GitAuth::Ssh {
+                ref private_key,
+                ref passphrase,
+                ref username,
+            } if allowed.contains(CredentialType::SSH_MEMORY) => Cred::ssh_key_from_memory(
+                username.as_deref().or(username_from_url).unwrap_or("git"),
+                None,
+                private_key,
+                passphrase.as_deref(),
+            ),
+            GitAuth::Https {
+                ref username,
+                ref token,
+            } if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) => {
+                Cred::userpass_plaintext(username, token)
+            }
+            _ => Err(git2::Error::new(
+                ErrorCode::Auth,
+                ErrorClass::Callback,
+                format!(
+                    "credential {} doesn't match the authentication asked by the remote",
+                    credential.name
+                ),
+            )),
+        }
+    });
+    callbacks.certificate_check(move |certificate, host| {
+        // Tls certificates are checked by libgit2 against the system trust store
+        let Some(hostkey) = certificate.as_hostkey() else {
+            return Ok(CertificateCheckStatus::CertificatePassthrough);
+        };
+        let Some(key) = hostkey.hostkey() else {
+            return Err(git2::Error::new(
+                ErrorCode::Certificate,
+                ErrorClass::Ssh,
+                format!("no host key received from {}", host),
+            ));
+        };
+        if known_host(known_hosts, host, key) {
+            Ok(CertificateCheckStatus::CertificateOk)
+        } else {
+            Err(git2::Error::new(
+                ErrorCode::Certificate,
+                ErrorClass::Ssh,
+                format!(
+                    "host key of {} is not in {}, add it with ssh-keyscan",
+                    host,
+                    known_hosts.display()
+                ),
+            ))
+        }
+    });
+    callbacks
+}
+
+/// Look for the key of the host in an OpenSSH known hosts file, hashed hosts are not supported
+fn known_host(known_hosts: &Path, host: &str, key: &[u8]) -> bool {
+    let Ok(content) = fs::read_to_string(known_hosts) else {
+        return false;
+    };
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('@'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((fields.next()?, fields.nth(1)?))
+        })
+        .filter(|(hosts, _)| {
+            hosts.split(',').any(|pattern| {
+                pattern == host
+                    || pattern
+                        .strip_prefix(&format!("[{}]:", host))
+                        .is_some_and(|port| port.parse::<u16>().is_ok())
+            })
+        })
+        .any(|(_, known_key)| {
+            STANDARD
+                .decode(known_key)
+                .is_ok_and(|known_key| known_key == key)
+        })
+}
+
+/// A clone only has the default branch locally, other branches are looked up on the origin remote
+fn resolve<'r>(repository: &'r Repository, reference: &str) -> Result<Object<'r>, Error> {
+    [
+        format!("refs/remotes/origin/{}", reference),
+        format!("refs/tags/{}", reference),
+        reference.to_string(),
+    ]
+    .iter()
+    .find_map(|spec| repository.revparse_single(spec).ok())
+    .ok_or(anyhow!(
+        "Git reference {} not found as a branch, a tag or a commit",
+        reference
+    ))
+}
+
+/// Directory of the sources to build, rejecting paths escaping the cloned repository
+pub fn source_directory(local_dir: &Path, directory: Option<&str>) -> Result<PathBuf, Error> {
+    let Some(directory) = directory else {
+        return Ok(local_dir.to_path_buf());
+    };
+    if Path::new(directory)
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!(
+            "Source directory {} must be relative to the repository root",
+            directory
+        ));
+    }
+    let path = local_dir.join(directory);
+    if !path.is_dir() {
+        return Err(anyhow!(
+            "Source directory {} not found in the repository",
+            directory
+        ));
+    }
+    Ok(path)
+}
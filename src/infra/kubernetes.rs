@@ -1,27 +1,64 @@
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    pin::Pin,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, Context, Error};
 use axum::async_trait;
-use k8s_openapi::api::{
-    apps::v1::Deployment,
-    core::v1::{Pod, Service},
-    networking::v1::Ingress,
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{
+    stream::{self, BoxStream},
+    AsyncBufReadExt, FutureExt, SinkExt, StreamExt,
+};
+use k8s_openapi::{
+    api::{
+        apps::v1::{Deployment, ReplicaSet, StatefulSet},
+        autoscaling::v2::HorizontalPodAutoscaler,
+        core::v1::{
+            ConfigMap, ContainerState as PodContainerState, PersistentVolumeClaim, Pod, Secret,
+            Service,
+        },
+        networking::v1::Ingress,
+    },
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, Status},
+    chrono::DateTime,
 };
 use kube::{
-    api::{DeleteParams, ListParams, PostParams},
-    Api, Client,
+    api::{
+        AttachParams, DeleteParams, DynamicObject, ListParams, LogParams, PostParams,
+        TerminalSize as PodTerminalSize,
+    },
+    Api, Client, Resource, ResourceExt,
 };
-use log::info;
+use itertools::Itertools;
+use log::{debug, warn};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    sync::mpsc,
+    time::sleep,
+};
 
 use crate::{
     config::{KubernetesConfig, RoutingConfig},
     domain::{
-        model::{Application, Container},
-        port::ContainerExecutor,
+        model::{
+            AddonKind, Application, Autoscaling, Container, ContainerState, Environment, Health, HealthCheck, HealthProbe,
+            ExecOptions, LogLine, LogOptions, Process, ProcessLoad, RegistryCredential, Resources, RunOutput,
+            SourceCredentials, TerminalSize, WEB_PROCESS,
+        },
+        port::{ContainerExecutor, DeploymentReporter, ExecSession},
     },
+    infra::routing::{traefik_middleware_resource, Route},
 };
 
+/// Time a one-off pod has to be scheduled and pull its image
+const POD_START_TIMEOUT: Duration = Duration::from_secs(300);
+
 pub struct KubernetesContainerExecutor {
     pub kube_config: KubernetesConfig,
     pub routing_config: RoutingConfig,
@@ -30,9 +67,41 @@ pub struct KubernetesContainerExecutor {
 
 #[async_trait]
 impl ContainerExecutor for KubernetesContainerExecutor {
-    async fn register_image(&self, application: &Application) -> Result<String, Error> {
+    async fn register_image(
+        &self,
+        application: &Application,
+        credentials: &SourceCredentials,
+        reporter: &dyn DeploymentReporter,
+    ) -> Result<String, Error> {
         match application.source {
             crate::domain::model::ApplicationSource::DockerImage { ref image, pull: _ } => {
+                // Images are pulled by the kubelet, the registry credential is given to pods as a pull secret
+                let pull_secret_name = format!("{}-registry", application.name);
+                let secrets: Api<Secret> =
+                    Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+                match credentials.registry {
+                    Some(ref credential) => {
+                        reporter
+                            .log(format!("Use registry credential of {}", credential.registry))
+                            .await;
+                        let secret: Secret = serde_json::from_value(json!({
+                            "apiVersion": "v1",
+                            "kind": "Secret",
+                            "metadata": {
+                                "name": pull_secret_name.clone(),
+                                "labels": {
+                                    "cleverclown.app": application.name.clone(),
+                                },
+                            },
+                            "type": "kubernetes.io/dockerconfigjson",
+                            "stringData": {
+                                ".dockerconfigjson": docker_config_json(credential).to_string(),
+                            },
+                        }))?;
+                        apply(&secrets, pull_secret_name.as_str(), &secret).await?;
+                    }
+                    None => delete_opt(&secrets, pull_secret_name.as_str()).await?,
+                }
                 Ok(image.clone())
             }
             _ => Err(anyhow!("Kubernetes runtime only support DockerImage application source")),
@@ -50,7 +119,7 @@ impl ContainerExecutor for KubernetesContainerExecutor {
                 ..Default::default()
             })
             .await?;
-        if app_deployment.items.len() == 0 {
+        if app_deployment.items.is_empty() {
             return Ok(vec![]);
         }
         let pods = pods
@@ -61,228 +130,277 @@ impl ContainerExecutor for KubernetesContainerExecutor {
             .await?;
         Ok(pods
             .into_iter() // TODO manage unwraps
-            .map(|pod| Container {
-                id: pod.metadata.name.unwrap(),
-                started_at: pod
-                    .metadata
-                    .creation_timestamp
-                    .map(|x| wrap_to_u64(x.0.timestamp_millis()))
-                    .unwrap(),
-                image_id: pod
-                    .spec
-                    .and_then(|spec| spec.containers.get(0).cloned())
-                    .and_then(|container| container.image)
-                    .unwrap(),
+            .map(|pod| {
+                let status = pod
+                    .status
+                    .and_then(|status| status.container_statuses)
+                    .and_then(|statuses| statuses.into_iter().next());
+                let state = status.as_ref().and_then(|status| status.state.as_ref());
+                Container {
+                    // Pods started before processes were introduced run the web one
+                    process: pod
+                        .metadata
+                        .labels
+                        .as_ref()
+                        .and_then(|labels| labels.get("cleverclown.process").cloned())
+                        .unwrap_or(WEB_PROCESS.to_string()),
+                    id: pod.metadata.name.unwrap(),
+                    // In seconds like the Docker runtime
+                    started_at: pod
+                        .metadata
+                        .creation_timestamp
+                        .map(|x| u64::try_from(x.0.timestamp()).unwrap_or_default())
+                        .unwrap(),
+                    image_id: pod
+                        .spec
+                        .and_then(|spec| spec.containers.first().cloned())
+                        .and_then(|container| container.image)
+                        .unwrap(),
+                    state: if state.is_some_and(|state| state.running.is_some()) {
+                        ContainerState::Running
+                    } else if state.is_some_and(|state| state.terminated.is_some()) {
+                        ContainerState::Exited
+                    } else if state
+                        .and_then(|state| state.waiting.as_ref())
+                        .and_then(|waiting| waiting.reason.as_deref())
+                        == Some("CrashLoopBackOff")
+                    {
+                        ContainerState::Restarting
+                    } else {
+                        ContainerState::Pending
+                    },
+                    restart_count: status
+                        .as_ref()
+                        .and_then(|status| u32::try_from(status.restart_count).ok())
+                        .unwrap_or(0),
+                    health: status.map(|status| {
+                        if status.ready {
+                            Health::Healthy
+                        } else {
+                            Health::Starting
+                        }
+                    }),
+                    // Deployments roll out pods on environment checksum changes
+                    checksum: None,
+                }
             })
             .collect())
     }
 
+    async fn register_volumes(&self, application: &Application) -> Result<(), Error> {
+        // Claims are never replaced, their storage outlives the deployments
+        let claims: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        for volume in application.volumes() {
+            let name = claim_name(&application.name, &volume.name);
+            if let Some(claim) = claims.get_opt(name.as_str()).await? {
+                let labels = claim.metadata.labels.unwrap_or_default();
+                if labels.get("cleverclown.app") != Some(&application.name)
+                    || labels.get("cleverclown.volume") != Some(&volume.name)
+                {
+                    return Err(anyhow!(
+                        "Volume claim {} already exists and doesn't belong to application {}",
+                        name,
+                        application.name
+                    ));
+                }
+            } else {
+                let claim: PersistentVolumeClaim = serde_json::from_value(json!({
+                    "apiVersion": "v1",
+                    "kind": "PersistentVolumeClaim",
+                    "metadata": {
+                        "name": name.clone(),
+                        "labels": {
+                            "cleverclown.app": application.name.clone(),
+                            "cleverclown.volume": volume.name.clone(),
+                        },
+                    },
+                    "spec": {
+                        "accessModes": ["ReadWriteOnce"],
+                        "resources": {
+                            "requests": { "storage": format!("{}Mi", volume.size()) },
+                        },
+                    },
+                }))?;
+                claims
+                    .create(&PostParams::default(), &claim)
+                    .await
+                    .with_context(|| format!("Error while creating volume {}", volume.name))?;
+            }
+        }
+        Ok(())
+    }
+
     async fn register_application(
         &self,
         application: &Application,
         image_id: String,
+        environment: &Environment,
     ) -> Result<Vec<Container>, Error> {
-        let deployments: Api<Deployment> =
+        let env_name = format!("{}-env", application.name);
+        let config_maps: Api<ConfigMap> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
-
-        let app_deployment: Deployment = serde_json::from_value(json!({
-            "apiVersion": "apps/v1",
-            "kind": "Deployment",
+        let config_map: ConfigMap = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
             "metadata": {
-                "name": application.name.clone(),
+                "name": env_name.clone(),
                 "labels": {
                     "cleverclown.app": application.name.clone(),
                 },
             },
-            "spec": {
-                "replicas": application.configuration.as_ref().and_then(|cfg| cfg.replicas).clone(),
-                "selector": {
-                    "matchLabels": {
-                        "cleverclown.app": application.name.clone(),
-                    },
-                },
-                "template": {
-                    "metadata": {
-                        "labels": {
-                            "cleverclown.app": application.name.clone(),
-                        },
-                    },
-                    "spec": {
-                        "containers": [
-                            {
-                            "name": "application",
-                            "image": image_id,
-                            "ports": [
-                                {
-                                    "containerPort" : application.configuration.as_ref().and_then(|cfg| cfg.exposed_port).clone()
-                                }
-                            ]
-                            }
-                        ]
-                    }
-                },
-            }
+            "data": environment.variables,
         }))?;
-        if deployments
-            .list(&ListParams {
-                label_selector: Some(format!("cleverclown.app={}", application.name.as_str())),
-                ..Default::default()
-            })
-            .await?
-            .items
-            .len()
-            > 0
-        {
-            deployments
-                .replace(
-                    application.name.as_str(),
-                    &PostParams {
-                        ..Default::default()
-                    },
-                    &app_deployment,
-                )
-                .await?;
-        } else {
-            deployments
-                .create(
-                    &PostParams {
-                        ..Default::default()
-                    },
-                    &app_deployment,
-                )
-                .await?;
-        }
+        apply(&config_maps, env_name.as_str(), &config_map).await?;
 
-        let services: Api<Service> =
+        let secrets: Api<Secret> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
-        let service: Service = serde_json::from_value(json!({
+        let secret: Secret = serde_json::from_value(json!({
             "apiVersion": "v1",
-            "kind": "Service",
+            "kind": "Secret",
             "metadata": {
-                "name": application.name.clone(),
+                "name": env_name.clone(),
                 "labels": {
                     "cleverclown.app": application.name.clone(),
                 },
             },
-            "spec": {
-                "ports": [
-                    {
-                        "name": "app",
-                     "port": application.configuration.as_ref().and_then(|cfg| cfg.exposed_port).clone(),
-                     "targetPort": application.configuration.as_ref().and_then(|cfg| cfg.exposed_port).clone()
-                    }
-                ],
-            "selector": {
-                "cleverclown.app": application.name.clone()
-            }
-        }
+            "type": "Opaque",
+            "stringData": environment.secrets,
         }))?;
-        if services
-            .list(&ListParams {
-                label_selector: Some(format!("cleverclown.app={}", application.name.as_str())),
-                ..Default::default()
-            })
-            .await?
-            .items
-            .len()
-            > 0
-        {
-            services
-                .replace(
-                    application.name.as_str(),
-                    &PostParams {
-                        ..Default::default()
-                    },
-                    &service,
-                )
-                .await?;
-        } else {
-            services
-                .create(
-                    &PostParams {
-                        ..Default::default()
-                    },
-                    &service,
-                )
-                .await?;
-        }
+        apply(&secrets, env_name.as_str(), &secret).await?;
 
-        let ingresses: Api<Ingress> =
+        let pull_secret_name = format!("{}-registry", application.name);
+        let image_pull_secrets = match secrets.get_opt(pull_secret_name.as_str()).await? {
+            Some(_) => vec![json!({ "name": pull_secret_name })],
+            None => vec![],
+        };
+
+        let deployments: Api<Deployment> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
-        let ingress: Ingress = serde_json::from_value(json!({
-                "apiVersion": "networking.k8s.io/v1",
-                "kind": "Ingress",
+        let autoscalers: Api<HorizontalPodAutoscaler> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let exposed_port = application
+            .configuration
+            .as_ref()
+            .and_then(|configuration| configuration.exposed_port);
+
+        // One Deployment per process, all of them running the application image
+        let processes = application.processes();
+        let mut desired_replicas = 0;
+        for process in processes.iter() {
+            let name = deployment_name(&application.name, &process.name);
+            let healthcheck = application.healthcheck(process);
+            // Replicas of an autoscaled process are left to its HorizontalPodAutoscaler
+            let replicas = match process.autoscaling {
+                Some(_) => deployments
+                    .get_opt(name.as_str())
+                    .await?
+                    .and_then(|deployment| deployment.spec)
+                    .and_then(|spec| spec.replicas)
+                    .unwrap_or(i32::from(process.replicas())),
+                None => i32::from(process.replicas()),
+            };
+            desired_replicas += usize::try_from(replicas).unwrap_or_default();
+            let process_deployment: Deployment = serde_json::from_value(json!({
+                "apiVersion": "apps/v1",
+                "kind": "Deployment",
                 "metadata": {
-                    "name": application.name.clone(),
+                    "name": name.clone(),
                     "labels": {
                         "cleverclown.app": application.name.clone(),
-
+                        "cleverclown.process": process.name.clone(),
                     },
                 },
                 "spec": {
-                    "rules":[
-                        {
-                            "host": format!("{}.{}", application.configuration.as_ref().and_then(|cfg| cfg.domain.clone()).unwrap_or(application.name.clone()),
-                                self.routing_config.domain),
-                            "http": {
-                                "paths": [
-                                {"path": "/",
-                                "pathType": "Prefix",
-                                "backend": {
-                                    "service": {
-                                        "name": application.name.clone(),
-                                        "port": {
-                                            "name": "app"
-                                        }
-                                    }
-                                }}
-                                ]
-                            }
+                    "replicas": replicas,
+                    // New pods must be ready before outdated ones are removed
+                    "strategy": {
+                        "type": "RollingUpdate",
+                        "rollingUpdate": { "maxUnavailable": 0, "maxSurge": 1 },
+                    },
+                    "progressDeadlineSeconds": healthcheck.map(|healthcheck| (healthcheck.interval() + healthcheck.timeout()) * (u64::from(healthcheck.retries()) + 1)),
+                    "selector": {
+                        "matchLabels": {
+                            "cleverclown.app": application.name.clone(),
+                            "cleverclown.process": process.name.clone(),
+                        },
+                    },
+                    "template": {
+                        "metadata": {
+                            "labels": {
+                                "cleverclown.app": application.name.clone(),
+                                "cleverclown.process": process.name.clone(),
+                            },
+                            "annotations": {
+                                // Environment is referenced from pods, a change of checksum rolls them out
+                                "cleverclown.env/checksum": environment.checksum(),
+                            },
+                        },
+                        "spec": {
+                            "imagePullSecrets": image_pull_secrets,
+                            "volumes": pod_volumes(application),
+                            "containers": [
+                                {
+                                "name": "application",
+                                "image": image_id,
+                                "args": process.command.clone(),
+                                "readinessProbe": healthcheck.map(|healthcheck| readiness_probe(healthcheck, exposed_port)),
+                                "resources": kubernetes_resources(&environment.resources),
+                                "volumeMounts": volume_mounts(application),
+                                "envFrom": [
+                                    { "configMapRef": { "name": env_name.clone() } },
+                                    { "secretRef": { "name": env_name.clone() } },
+                                ],
+                                // A null containerPort is read as 0 and rejected, only the routed process declares it
+                                "ports": exposed_port
+                                    .filter(|_| process.routed())
+                                    .map(|exposed_port| vec![json!({ "containerPort": exposed_port })])
+                                    .unwrap_or_default(),
+                                }
+                            ]
                         }
-                    ]
+                    },
                 }
-        }))?;
-        if ingresses
+            }))?;
+            apply(&deployments, name.as_str(), &process_deployment).await?;
+            match process.autoscaling {
+                Some(ref autoscaling) => {
+                    let autoscaler = horizontal_pod_autoscaler(application, process, autoscaling, environment)?;
+                    apply(&autoscalers, name.as_str(), &autoscaler).await?
+                }
+                None => delete_opt(&autoscalers, name.as_str()).await?,
+            }
+        }
+        // Deployments of removed processes, or created before processes were introduced
+        let declared = processes
+            .iter()
+            .map(|process| deployment_name(&application.name, &process.name))
+            .collect::<Vec<_>>();
+        for stale in deployments
             .list(&ListParams {
-                label_selector: Some(format!("cleverclown.app={}", application.name.as_str())),
+                label_selector: Some(format!("cleverclown.app={}", application.name)),
                 ..Default::default()
             })
             .await?
-            .items
-            .len()
-            > 0
+            .into_iter()
+            .filter(|deployment| !declared.contains(&deployment.name_any()))
         {
-            ingresses
-                .replace(
-                    application.name.as_str(),
-                    &PostParams {
-                        ..Default::default()
-                    },
-                    &ingress,
-                )
-                .await?;
+            delete_opt(&deployments, stale.name_any().as_str()).await?;
+            delete_opt(&autoscalers, stale.name_any().as_str()).await?;
+        }
+
+        let route = Route::new(application, &self.routing_config);
+        if application.routed() {
+            self.register_route(&route, exposed_port, environment).await?
         } else {
-            ingresses
-                .create(
-                    &PostParams {
-                        ..Default::default()
-                    },
-                    &ingress,
-                )
-                .await?;
+            // Routing of an application formerly having a web process is removed
+            self.delete_route(&route).await?
         }
 
         let mut instances = self.running(application.name.clone()).await?;
         let started = Instant::now();
-        while instances.len()
-            < application
-                .configuration
-                .as_ref()
-                .and_then(|config| config.replicas)
-                .unwrap_or(1)
-                .into()
-            && started.elapsed().as_millis() < 5000
-        {
+        while instances.len() < desired_replicas && started.elapsed().as_millis() < 5000 {
+            sleep(Duration::from_millis(250)).await;
             instances = self.running(application.name.clone()).await?;
         }
         if started.elapsed().as_millis() >= 5000 {
@@ -292,66 +410,388 @@ impl ContainerExecutor for KubernetesContainerExecutor {
         }
     }
 
-    async fn delete_application(&self, application: String) -> Result<(), Error> {
-        let deployments: Api<Deployment> =
+    async fn delete_application(&self, application: String, purge_volumes: bool) -> Result<(), Error> {
+        let config_maps: Api<ConfigMap> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
-        let services: Api<Service> =
+        let secrets: Api<Secret> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
-        let ingresses: Api<Ingress> =
+        let deployments: Api<Deployment> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
 
-        let _ = ingresses
-            .delete(
-                application.as_str(),
-                &DeleteParams {
+        let _ = self.delete_route(&Route::named(&application)).await;
+        let _ = deployments
+            .delete_collection(
+                &DeleteParams::default(),
+                &ListParams {
+                    label_selector: Some(format!("cleverclown.app={}", application)),
                     ..Default::default()
                 },
             )
             .await;
-
-        let _ = services
-            .delete(
-                application.as_str(),
-                &DeleteParams {
+        let autoscalers: Api<HorizontalPodAutoscaler> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let _ = autoscalers
+            .delete_collection(
+                &DeleteParams::default(),
+                &ListParams {
+                    label_selector: Some(format!("cleverclown.app={}", application)),
                     ..Default::default()
                 },
             )
             .await;
-        let _ = deployments
-            .delete(
-                application.as_str(),
-                &DeleteParams {
-                    ..Default::default()
-                },
-            )
+        let env_name = format!("{}-env", application);
+        let _ = config_maps
+            .delete(env_name.as_str(), &DeleteParams::default())
             .await;
+        let _ = secrets
+            .delete(env_name.as_str(), &DeleteParams::default())
+            .await;
+        let _ = secrets
+            .delete(format!("{}-registry", application).as_str(), &DeleteParams::default())
+            .await;
+        if purge_volumes {
+            let claims: Api<PersistentVolumeClaim> =
+                Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+            claims
+                .delete_collection(
+                    &DeleteParams::default(),
+                    &ListParams {
+                        label_selector: Some(format!("cleverclown.app={},cleverclown.volume", application)),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .context("Error while removing volumes")?;
+        }
 
         Ok(())
     }
 
+    async fn volumes(&self, application_name: String) -> Result<Vec<String>, Error> {
+        let claims: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        Ok(claims
+            .list(&ListParams {
+                label_selector: Some(format!("cleverclown.app={},cleverclown.volume", application_name)),
+                ..Default::default()
+            })
+            .await?
+            .into_iter()
+            .filter_map(|claim| claim.labels().get("cleverclown.volume").cloned())
+            .sorted()
+            .collect())
+    }
+
     async fn start_instance(
         &self,
         application: &Application,
+        process: &Process,
         image_id: String,
+        _environment: &Environment,
     ) -> Result<Container, Error> {
         Ok(Container {
-            id: application.name.clone(),
-            image_id: image_id,
+            id: deployment_name(&application.name, &process.name),
+            process: process.name.clone(),
+            image_id,
             started_at: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .expect("Time went backward")
                 .as_secs(),
+            state: ContainerState::Pending,
+            restart_count: 0,
+            health: None,
+            checksum: None,
         })
     }
 
     async fn stop_instance(
         &self,
-        _application: String,
-        _container: &Container,
+        application: String,
+        container: &Container,
     ) -> Result<(), Error> {
+        // Pods are removed by their Deployment, only an aborted rollout started by start_instance is undone
+        let name = deployment_name(&application, &container.process);
+        if container.id == name {
+            self.rollback_deployment(&application, &container.process).await?;
+        }
         Ok(())
     }
 
+    async fn instance_health(
+        &self,
+        application_name: String,
+        container: &Container,
+    ) -> Result<Health, Error> {
+        // Pods are rolled out by the Deployment of the process, its status tells if the rollout went through
+        let deployments: Api<Deployment> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let deployment = deployments
+            .get(deployment_name(&application_name, &container.process).as_str())
+            .await?;
+        let replicas = deployment
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.replicas)
+            .unwrap_or(1);
+        let Some(status) = deployment.status else {
+            return Ok(Health::Starting);
+        };
+        // Conditions of a previous rollout are kept until the new spec is observed
+        if status.observed_generation < deployment.metadata.generation {
+            return Ok(Health::Starting);
+        }
+        if status
+            .conditions
+            .iter()
+            .flatten()
+            .any(|condition| condition.reason.as_deref() == Some("ProgressDeadlineExceeded"))
+        {
+            return Ok(Health::Unhealthy);
+        }
+        let rolled_out = status.observed_generation >= deployment.metadata.generation
+            && status.updated_replicas.unwrap_or(0) == replicas
+            && status.available_replicas.unwrap_or(0) == replicas;
+        Ok(if rolled_out {
+            Health::Healthy
+        } else {
+            Health::Starting
+        })
+    }
+
+    async fn logs(
+        &self,
+        _application_name: String,
+        containers: Vec<Container>,
+        options: &LogOptions,
+    ) -> Result<BoxStream<'static, Result<LogLine, Error>>, Error> {
+        let log_params = LogParams {
+            container: Some("application".to_string()),
+            follow: options.follow,
+            tail_lines: options.tail.map(i64::from),
+            since_time: options
+                .since
+                .and_then(|since| i64::try_from(since).ok())
+                .and_then(|since| DateTime::from_timestamp(since, 0)),
+            ..Default::default()
+        };
+        // The pod log reader borrows its api, each pod is read by its own task feeding the stream
+        let (sender, receiver) = mpsc::channel(1024);
+        for container in containers {
+            let pods: Api<Pod> =
+                Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+            let log_params = log_params.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                let mut lines = match pods.log_stream(&container.id, &log_params).await {
+                    Ok(reader) => reader.lines(),
+                    Err(e) => {
+                        let _ = sender
+                            .send(Err(Error::from(e).context(format!(
+                                "Error while reading logs of pod {}",
+                                container.id
+                            ))))
+                            .await;
+                        return;
+                    }
+                };
+                while let Some(line) = lines.next().await {
+                    let line = line.map(|message| LogLine {
+                        instance: container.id.clone(),
+                        message,
+                    });
+                    // Stop reading once the client is gone
+                    if sender.send(line.map_err(Error::from)).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|line| (line, receiver))
+        })
+        .boxed())
+    }
+
+    async fn run(
+        &self,
+        application: &Application,
+        image_id: String,
+        environment: &Environment,
+        command: Vec<String>,
+        timeout: Option<Duration>,
+    ) -> Result<BoxStream<'static, Result<RunOutput, Error>>, Error> {
+        let name = format!(
+            "{}-run-{}",
+            application.name,
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(7)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect::<String>()
+        );
+        let secrets: Api<Secret> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let pods: Api<Pod> = Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        // The environment of the application may not be registered yet, the pod gets its own
+        let secret: Secret = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {
+                "name": name.clone(),
+                "labels": {
+                    "cleverclown.run": application.name.clone(),
+                },
+            },
+            "type": "Opaque",
+            "stringData": environment
+                .variables
+                .iter()
+                .chain(environment.secrets.iter())
+                .collect::<BTreeMap<_, _>>(),
+        }))?;
+        secrets.create(&PostParams::default(), &secret).await?;
+        let pull_secret_name = format!("{}-registry", application.name);
+        let image_pull_secrets = match secrets.get_opt(pull_secret_name.as_str()).await? {
+            Some(_) => vec![json!({ "name": pull_secret_name })],
+            None => vec![],
+        };
+        // Not labelled as an application pod, so it is never converged
+        let pod: Pod = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Pod",
+            "metadata": {
+                "name": name.clone(),
+                "labels": {
+                    "cleverclown.run": application.name.clone(),
+                },
+            },
+            "spec": {
+                "restartPolicy": "Never",
+                "imagePullSecrets": image_pull_secrets,
+                "volumes": pod_volumes(application),
+                "containers": [
+                    {
+                        "name": "application",
+                        "image": image_id,
+                        "args": command,
+                        "resources": kubernetes_resources(&environment.resources),
+                        "volumeMounts": volume_mounts(application),
+                        "envFrom": [
+                            { "secretRef": { "name": name.clone() } },
+                        ],
+                    }
+                ],
+            },
+        }))?;
+        if let Err(e) = pods.create(&PostParams::default(), &pod).await {
+            let _ = secrets.delete(name.as_str(), &DeleteParams::default()).await;
+            return Err(Error::from(e).context("Error while creating one-off pod"));
+        }
+        // The pod is followed in its own task, so it is removed even if the output isn't read until the end
+        let (sender, receiver) = mpsc::channel(1024);
+        tokio::spawn(async move {
+            let output = async {
+                wait_pod_started(&pods, &name).await?;
+                let mut lines = pods
+                    .log_stream(
+                        &name,
+                        &LogParams {
+                            container: Some("application".to_string()),
+                            follow: true,
+                            ..Default::default()
+                        },
+                    )
+                    .await?
+                    .lines();
+                while let Some(line) = lines.next().await {
+                    let _ = sender.send(Ok(RunOutput::Line(line?))).await;
+                }
+                wait_pod_exit_code(&pods, &name).await.map(RunOutput::Exited)
+            };
+            let end = match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, output)
+                    .await
+                    .unwrap_or(Ok(RunOutput::TimedOut)),
+                None => output.await,
+            };
+            if let Err(e) = pods.delete(&name, &DeleteParams::default()).await {
+                warn!("One-off pod {} can't be deleted: {:?}", name, e);
+            }
+            let _ = secrets.delete(&name, &DeleteParams::default()).await;
+            let _ = sender.send(end).await;
+        });
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|output| (output, receiver))
+        })
+        .boxed())
+    }
+
+    async fn exec(
+        &self,
+        _application_name: String,
+        container: &Container,
+        options: &ExecOptions,
+    ) -> Result<ExecSession, Error> {
+        let pods: Api<Pod> = Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let mut attached = pods
+            .exec(
+                &container.id,
+                options.command(),
+                &AttachParams::default()
+                    .container("application")
+                    .stdin(options.stdin)
+                    .stdout(true)
+                    // A terminal merges stderr in stdout
+                    .stderr(!options.tty)
+                    .tty(options.tty),
+            )
+            .await
+            .context(format!("Error while starting exec in pod {}", container.id))?;
+        let output = stream::select_all(
+            attached
+                .stdout()
+                .map(read_chunks)
+                .into_iter()
+                .chain(attached.stderr().map(read_chunks)),
+        )
+        .boxed();
+        let input = attached
+            .stdin()
+            .map(|stdin| Box::pin(stdin) as Pin<Box<dyn AsyncWrite + Send>>);
+        let resize = attached.terminal_size().map(|mut terminal_size| {
+            let (sender, mut receiver) = mpsc::channel::<TerminalSize>(8);
+            // Ends with the session, once its sender is dropped
+            tokio::spawn(async move {
+                while let Some(size) = receiver.recv().await {
+                    let size = PodTerminalSize {
+                        width: size.width,
+                        height: size.height,
+                    };
+                    if terminal_size.send(size).await.is_err() {
+                        return;
+                    }
+                }
+            });
+            sender
+        });
+        let status = attached.take_status();
+        Ok(ExecSession {
+            output,
+            input,
+            resize,
+            exit_code: async move {
+                let status = match status {
+                    Some(status) => status.await,
+                    None => None,
+                };
+                // The exec connection is kept open by the attached process until the command ended
+                drop(attached);
+                status.map(exec_exit_code).transpose()
+            }
+            .boxed(),
+        })
+    }
+
     async fn list_applications(&self) -> Result<Vec<String>, Error> {
         let deployments: Api<Deployment> =
             Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
@@ -360,26 +800,593 @@ impl ContainerExecutor for KubernetesContainerExecutor {
                 ..Default::default()
             })
             .await?;
+        // Each process of an application has its own Deployment
         Ok(applications
             .into_iter()
-            .filter(|deployment| {
-                deployment
-                    .metadata
-                    .labels
-                    .as_ref()
-                    .filter(|labels| labels.contains_key("cleverclown.app"))
-                    .is_some()
-            })
-            .map(|deployment| deployment.metadata.name.unwrap())
+            .filter_map(|deployment| deployment.labels().get("cleverclown.app").cloned())
+            .unique()
             .collect())
     }
 
     async fn ensure_routing(&self) -> Result<(), Error> {
-        info!("TODO - Check traefik is installed");
+        // Called on every background reconciliation
+        debug!("TODO - Check traefik is installed");
+        Ok(())
+    }
+
+    fn scales_processes(&self) -> bool {
+        // HorizontalPodAutoscalers set the replicas of autoscaled Deployments
+        true
+    }
+
+    async fn process_load(
+        &self,
+        _application: &Application,
+        _process: &Process,
+        _containers: &[Container],
+    ) -> Result<Option<ProcessLoad>, Error> {
+        // Scaled by the HorizontalPodAutoscaler of the process
+        Ok(None)
+    }
+
+    async fn create_addon(
+        &self,
+        name: String,
+        kind: AddonKind,
+        password: String,
+    ) -> Result<String, Error> {
+        let resource_name = addon_name(&name);
+        let secrets: Api<Secret> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let secret: Secret = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {
+                "name": resource_name.clone(),
+                "labels": {
+                    "cleverclown.addon": name.clone(),
+                },
+            },
+            "type": "Opaque",
+            "stringData": { "password": password },
+        }))?;
+        apply(&secrets, resource_name.as_str(), &secret).await?;
+
+        let password_env = |variable: &str| {
+            json!({
+                "name": variable,
+                "valueFrom": { "secretKeyRef": { "name": resource_name.clone(), "key": "password" } },
+            })
+        };
+        let (env, args) = match kind {
+            AddonKind::Postgres => (
+                json!([
+                    password_env("POSTGRES_PASSWORD"),
+                    // The volume root holds lost+found, postgres needs an empty directory
+                    { "name": "PGDATA", "value": format!("{}/pgdata", kind.data_path()) },
+                ]),
+                json!(null),
+            ),
+            AddonKind::Redis => (
+                json!([password_env("REDIS_PASSWORD")]),
+                json!(["redis-server", "--requirepass", "$(REDIS_PASSWORD)", "--appendonly", "yes"]),
+            ),
+        };
+        let stateful_sets: Api<StatefulSet> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let stateful_set: StatefulSet = serde_json::from_value(json!({
+            "apiVersion": "apps/v1",
+            "kind": "StatefulSet",
+            "metadata": {
+                "name": resource_name.clone(),
+                "labels": {
+                    "cleverclown.addon": name.clone(),
+                },
+            },
+            "spec": {
+                "replicas": 1,
+                "serviceName": resource_name.clone(),
+                "selector": {
+                    "matchLabels": {
+                        "cleverclown.addon": name.clone(),
+                    },
+                },
+                "template": {
+                    "metadata": {
+                        "labels": {
+                            "cleverclown.addon": name.clone(),
+                        },
+                    },
+                    "spec": {
+                        "containers": [
+                            {
+                                "name": "addon",
+                                "image": kind.image(),
+                                "args": args,
+                                "env": env,
+                                "ports": [{ "containerPort": kind.port() }],
+                                "volumeMounts": [{ "name": "data", "mountPath": kind.data_path() }],
+                            }
+                        ],
+                    },
+                },
+                // Claims of the template are kept by Kubernetes when the StatefulSet is deleted
+                "volumeClaimTemplates": [
+                    {
+                        "metadata": {
+                            "name": "data",
+                            "labels": {
+                                "cleverclown.addon": name.clone(),
+                            },
+                        },
+                        "spec": {
+                            "accessModes": ["ReadWriteOnce"],
+                            "resources": {
+                                "requests": { "storage": "1024Mi" },
+                            },
+                        },
+                    }
+                ],
+            },
+        }))?;
+        apply(&stateful_sets, resource_name.as_str(), &stateful_set).await?;
+
+        let services: Api<Service> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let service: Service = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": {
+                "name": resource_name.clone(),
+                "labels": {
+                    "cleverclown.addon": name.clone(),
+                },
+            },
+            "spec": {
+                "selector": {
+                    "cleverclown.addon": name.clone(),
+                },
+                "ports": [{ "port": kind.port(), "targetPort": kind.port() }],
+            },
+        }))?;
+        apply(&services, resource_name.as_str(), &service).await?;
+        Ok(resource_name)
+    }
+
+    async fn delete_addon(&self, name: String) -> Result<(), Error> {
+        let resource_name = addon_name(&name);
+        let stateful_sets: Api<StatefulSet> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let services: Api<Service> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let secrets: Api<Secret> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let claims: Api<PersistentVolumeClaim> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        delete_opt(&stateful_sets, resource_name.as_str()).await?;
+        delete_opt(&services, resource_name.as_str()).await?;
+        delete_opt(&secrets, resource_name.as_str()).await?;
+        claims
+            .delete_collection(
+                &DeleteParams::default(),
+                &ListParams {
+                    label_selector: Some(format!("cleverclown.addon={}", name)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .context("Error while removing add-on volume")?;
+        Ok(())
+    }
+}
+
+impl KubernetesContainerExecutor {
+    /// Put back the pod template of the previous revision of the process Deployment, as `kubectl rollout undo` does
+    async fn rollback_deployment(&self, application: &str, process: &str) -> Result<(), Error> {
+        let name = deployment_name(application, process);
+        let deployments: Api<Deployment> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let replica_sets: Api<ReplicaSet> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let mut deployment = deployments.get(name.as_str()).await?;
+        let revision = |metadata: &ObjectMeta| {
+            metadata
+                .annotations
+                .as_ref()
+                .and_then(|annotations| annotations.get("deployment.kubernetes.io/revision"))
+                .and_then(|revision| revision.parse::<u64>().ok())
+        };
+        let current = revision(&deployment.metadata);
+        let uid = deployment.metadata.uid.clone();
+        let previous = replica_sets
+            .list(&ListParams {
+                label_selector: Some(format!(
+                    "cleverclown.app={},cleverclown.process={}",
+                    application, process
+                )),
+                ..Default::default()
+            })
+            .await?
+            .into_iter()
+            .filter(|replica_set| {
+                replica_set
+                    .metadata
+                    .owner_references
+                    .iter()
+                    .flatten()
+                    .any(|owner| Some(&owner.uid) == uid.as_ref())
+            })
+            .filter(|replica_set| {
+                revision(&replica_set.metadata)
+                    .zip(current)
+                    .is_some_and(|(revision, current)| revision < current)
+            })
+            .max_by_key(|replica_set| revision(&replica_set.metadata));
+        // A first revision has nothing to roll back to
+        let Some(mut template) = previous
+            .and_then(|replica_set| replica_set.spec)
+            .and_then(|spec| spec.template)
+        else {
+            warn!("No previous revision of deployment {} to roll back to", name);
+            return Ok(());
+        };
+        if let Some(labels) = template.metadata.as_mut().and_then(|metadata| metadata.labels.as_mut()) {
+            labels.remove("pod-template-hash");
+        }
+        if let Some(spec) = deployment.spec.as_mut() {
+            spec.template = template;
+        }
+        deployments
+            .replace(name.as_str(), &PostParams::default(), &deployment)
+            .await
+            .context(format!("Error while rolling back deployment {}", name))?;
         Ok(())
     }
+
+    /// Service, ingresses and their middlewares and certificate routing requests to the pods
+    async fn register_route(
+        &self,
+        route: &Route,
+        exposed_port: Option<u16>,
+        environment: &Environment,
+    ) -> Result<(), Error> {
+        let services: Api<Service> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let service = route.kubernetes_service(exposed_port)?;
+        apply(&services, route.service.as_str(), &service).await?;
+
+        let secrets: Api<Secret> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let ingresses: Api<Ingress> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let middlewares: Api<DynamicObject> = Api::namespaced_with(
+            self.client.clone(),
+            &self.kube_config.app_namespace,
+            &traefik_middleware_resource(),
+        );
+        if route.redirected() {
+            apply(
+                &middlewares,
+                route.redirect_middleware().as_str(),
+                &route.kubernetes_redirect_middleware(),
+            )
+            .await?;
+        }
+        if route.stripped() {
+            apply(
+                &middlewares,
+                route.strip_middleware().as_str(),
+                &route.kubernetes_strip_middleware(),
+            )
+            .await?;
+        } else {
+            delete_opt(&middlewares, route.strip_middleware().as_str()).await?;
+        }
+        match environment.certificate {
+            Some(ref certificate) => {
+                apply(
+                    &secrets,
+                    route.kubernetes_tls_secret_name().as_str(),
+                    &route.kubernetes_tls_secret(certificate)?,
+                )
+                .await?
+            }
+            None => delete_opt(&secrets, route.kubernetes_tls_secret_name().as_str()).await?,
+        }
+        for ingress in route.kubernetes_ingresses(&self.kube_config.app_namespace)? {
+            apply(&ingresses, ingress.name_any().as_str(), &ingress).await?;
+        }
+        if route.tls.is_none() {
+            delete_opt(&ingresses, route.secure_router().as_str()).await?;
+        }
+        Ok(())
+    }
+
+    async fn delete_route(&self, route: &Route) -> Result<(), Error> {
+        let services: Api<Service> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let secrets: Api<Secret> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let ingresses: Api<Ingress> =
+            Api::namespaced(self.client.clone(), &self.kube_config.app_namespace);
+        let middlewares: Api<DynamicObject> = Api::namespaced_with(
+            self.client.clone(),
+            &self.kube_config.app_namespace,
+            &traefik_middleware_resource(),
+        );
+        delete_opt(&ingresses, route.router.as_str()).await?;
+        delete_opt(&ingresses, route.secure_router().as_str()).await?;
+        delete_opt(&middlewares, route.redirect_middleware().as_str()).await?;
+        delete_opt(&middlewares, route.strip_middleware().as_str()).await?;
+        delete_opt(&secrets, route.kubernetes_tls_secret_name().as_str()).await?;
+        delete_opt(&services, route.service.as_str()).await
+    }
+}
+
+/// Chunks read from an output of a command, ending on the first error
+fn read_chunks(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+) -> BoxStream<'static, Result<Vec<u8>, Error>> {
+    stream::unfold(Some(reader), |reader| async move {
+        let mut reader = reader?;
+        let mut buffer = vec![0; 4096];
+        match reader.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(buffer), Some(reader)))
+            }
+            Err(e) => Some((Err(Error::from(e)), None)),
+        }
+    })
+    .boxed()
+}
+
+/// Exit code of an exec from its final status, a non-zero one is only given as a failure cause
+fn exec_exit_code(status: Status) -> Result<i64, Error> {
+    if status.status.as_deref() == Some("Success") {
+        return Ok(0);
+    }
+    status
+        .details
+        .iter()
+        .flat_map(|details| details.causes.iter().flatten())
+        .find(|cause| cause.reason.as_deref() == Some("ExitCode"))
+        .and_then(|cause| cause.message.as_ref())
+        .and_then(|code| code.parse().ok())
+        .ok_or(anyhow!(
+            "Exec failed: {}",
+            status.message.unwrap_or_default()
+        ))
 }
 
-pub fn wrap_to_u64(x: i64) -> u64 {
-    (x as u64).wrapping_add(u64::MAX / 2 + 1)
+/// Wait for the container of the pod to start, failing when it can't be created or isn't scheduled in time.
+/// Scheduling isn't failed at once as a claim may still be provisioning
+async fn wait_pod_started(pods: &Api<Pod>, name: &str) -> Result<(), Error> {
+    let started = Instant::now();
+    loop {
+        let pod = pods.get(name).await?;
+        if let Some(state) = container_state(pod.clone()) {
+            if state.running.is_some() || state.terminated.is_some() {
+                return Ok(());
+            }
+            if let Some(reason) = state
+                .waiting
+                .and_then(|waiting| waiting.reason)
+                .filter(|reason| {
+                    [
+                        "ErrImagePull",
+                        "ImagePullBackOff",
+                        "InvalidImageName",
+                        "CreateContainerConfigError",
+                        "CreateContainerError",
+                    ]
+                    .contains(&reason.as_str())
+                })
+            {
+                return Err(anyhow!("Pod {} can't start: {}", name, reason));
+            }
+        }
+        if started.elapsed() >= POD_START_TIMEOUT {
+            let unscheduled = pod
+                .status
+                .iter()
+                .flat_map(|status| status.conditions.iter().flatten())
+                .find(|condition| condition.type_ == "PodScheduled" && condition.status == "False")
+                .and_then(|condition| condition.message.clone());
+            return Err(anyhow!(
+                "Pod {} not started after {}s{}",
+                name,
+                POD_START_TIMEOUT.as_secs(),
+                unscheduled
+                    .map(|message| format!(": {}", message))
+                    .unwrap_or_default()
+            ));
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn wait_pod_exit_code(pods: &Api<Pod>, name: &str) -> Result<i64, Error> {
+    loop {
+        if let Some(terminated) = pod_container_state(pods, name)
+            .await?
+            .and_then(|state| state.terminated)
+        {
+            return Ok(i64::from(terminated.exit_code));
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn pod_container_state(
+    pods: &Api<Pod>,
+    name: &str,
+) -> Result<Option<PodContainerState>, Error> {
+    Ok(container_state(pods.get(name).await?))
+}
+
+fn container_state(pod: Pod) -> Option<PodContainerState> {
+    pod.status
+        .and_then(|status| status.container_statuses)
+        .and_then(|statuses| statuses.into_iter().next())
+        .and_then(|status| status.state)
+}
+
+/// Name of the StatefulSet, Service and Secret of the add-on, the double dash keeps it apart from application resources
+fn addon_name(name: &str) -> String {
+    format!("addon--{}", name)
+}
+
+fn deployment_name(application: &str, process: &str) -> String {
+    format!("{}--{}", application, process)
+}
+
+/// The cpu target is an average value as Kubernetes utilization is relative to the cpu request.
+/// The requests target needs a custom metrics adapter serving `requests_per_second` for the application Service
+fn horizontal_pod_autoscaler(
+    application: &Application,
+    process: &Process,
+    autoscaling: &Autoscaling,
+    environment: &Environment,
+) -> Result<HorizontalPodAutoscaler, Error> {
+    let name = deployment_name(&application.name, &process.name);
+    let cpu = autoscaling
+        .target_cpu
+        .zip(environment.resources.cpu_limit)
+        .map(|(target, limit)| {
+            json!({
+                "type": "Resource",
+                "resource": {
+                    "name": "cpu",
+                    "target": { "type": "AverageValue", "averageValue": format!("{}m", limit * u32::from(target) / 100) },
+                },
+            })
+        });
+    let requests = autoscaling.target_requests.map(|target| {
+        json!({
+            "type": "Object",
+            "object": {
+                "describedObject": { "apiVersion": "v1", "kind": "Service", "name": application.name.clone() },
+                "metric": { "name": "requests_per_second" },
+                "target": { "type": "AverageValue", "averageValue": target.to_string() },
+            },
+        })
+    });
+    Ok(serde_json::from_value(json!({
+        "apiVersion": "autoscaling/v2",
+        "kind": "HorizontalPodAutoscaler",
+        "metadata": {
+            "name": name.clone(),
+            "labels": {
+                "cleverclown.app": application.name.clone(),
+                "cleverclown.process": process.name.clone(),
+            },
+        },
+        "spec": {
+            "scaleTargetRef": { "apiVersion": "apps/v1", "kind": "Deployment", "name": name },
+            "minReplicas": autoscaling.min,
+            "maxReplicas": autoscaling.max,
+            "metrics": cpu.into_iter().chain(requests).collect::<Vec<_>>(),
+        },
+    }))?)
+}
+
+fn claim_name(application: &str, volume: &str) -> String {
+    format!("{}--{}", application, volume)
+}
+
+fn pod_volumes(application: &Application) -> Vec<serde_json::Value> {
+    application
+        .volumes()
+        .iter()
+        .map(|volume| {
+            json!({
+                "name": volume.name,
+                "persistentVolumeClaim": { "claimName": claim_name(&application.name, &volume.name) },
+            })
+        })
+        .collect()
+}
+
+fn volume_mounts(application: &Application) -> Vec<serde_json::Value> {
+    application
+        .volumes()
+        .iter()
+        .map(|volume| json!({ "name": volume.name, "mountPath": volume.mount_path }))
+        .collect()
+}
+
+/// Content of a `kubernetes.io/dockerconfigjson` secret for the registry
+fn docker_config_json(credential: &RegistryCredential) -> serde_json::Value {
+    json!({
+        "auths": {
+            credential.server_address(): {
+                "username": credential.username,
+                "password": credential.password,
+                "auth": STANDARD.encode(format!("{}:{}", credential.username, credential.password)),
+            }
+        }
+    })
+}
+
+/// Delete the resource when it exists
+async fn delete_opt<K>(api: &Api<K>, name: &str) -> Result<(), Error>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+{
+    match api.delete(name, &DeleteParams::default()).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Create the resource or replace the existing one with the same name
+async fn apply<K>(api: &Api<K>, name: &str, resource: &K) -> Result<(), Error>
+where
+    K: Resource + Clone + DeserializeOwned + Serialize + Debug,
+{
+    match api.get_opt(name).await? {
+        Some(existing) => {
+            let mut resource = resource.clone();
+            resource.meta_mut().resource_version = existing.meta().resource_version.clone();
+            api.replace(name, &PostParams::default(), &resource).await?;
+        }
+        None => {
+            api.create(&PostParams::default(), resource).await?;
+        }
+    }
+    Ok(())
+}
+
+fn readiness_probe(healthcheck: &HealthCheck, exposed_port: Option<u16>) -> serde_json::Value {
+    let mut probe = match healthcheck.probe {
+        HealthProbe::Http { ref path, port } => json!({
+            "httpGet": { "path": path, "port": port.or(exposed_port) }
+        }),
+        HealthProbe::Tcp { port } => json!({
+            "tcpSocket": { "port": port.or(exposed_port) }
+        }),
+        HealthProbe::Command { ref command } => json!({
+            "exec": { "command": command }
+        }),
+    };
+    probe["periodSeconds"] = json!(healthcheck.interval());
+    probe["timeoutSeconds"] = json!(healthcheck.timeout());
+    probe["failureThreshold"] = json!(healthcheck.retries());
+    probe
+}
+
+fn kubernetes_resources(resources: &Resources) -> serde_json::Value {
+    let quantities = |cpu: Option<u32>, memory: Option<u32>| {
+        cpu.map(|cpu| ("cpu", format!("{}m", cpu)))
+            .into_iter()
+            .chain(memory.map(|memory| ("memory", format!("{}Mi", memory))))
+            .collect::<BTreeMap<_, _>>()
+    };
+    json!({
+        "limits": quantities(resources.cpu_limit, resources.memory_limit),
+        "requests": quantities(resources.cpu_request, resources.memory_request),
+    })
 }
@@ -1,3 +1,6 @@
 pub mod docker;
+pub mod file;
+pub mod git;
 pub mod kubernetes;
+pub mod routing;
 pub mod web;
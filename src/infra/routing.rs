@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Error;
+use k8s_openapi::api::{
+    core::v1::{Secret, Service},
+    networking::v1::Ingress,
+};
+use kube::api::{ApiResource, DynamicObject, GroupVersionKind};
+use serde_json::json;
+
+use crate::{
+    config::RoutingConfig,
+    domain::model::{Application, Certificate, Tls, WEB_PROCESS},
+};
+
+/// Name of the ACME certificate resolver configured on Traefik
+pub const ACME_RESOLVER: &str = "cleverclown";
+/// Directory watched by the Traefik file provider, holding the user supplied certificates
+pub const TRAEFIK_DYNAMIC_DIRECTORY: &str = "/etc/traefik/dynamic";
+
+/// Traefik routing of an application, the single place building it for both runtimes
+pub struct Route {
+    pub application: String,
+    /// Traefik router matching the application hosts on http, one per application.
+    /// Its other routing resources are suffixed after a double dash, which application names can't contain
+    pub router: String,
+    /// Traefik service balancing requests between the application instances, one per application
+    pub service: String,
+    pub hosts: Vec<String>,
+    /// Path prefixes matched on each host, `/` matching every path
+    pub paths: Vec<String>,
+    /// Remove the matched prefix before forwarding requests to the application
+    pub strip_prefix: bool,
+    pub tls: Option<Tls>,
+    /// Redirect http requests to https when served with tls
+    pub redirect: bool,
+}
+
+impl Route {
+    pub fn new(application: &Application, routing_config: &RoutingConfig) -> Self {
+        Self {
+            application: application.name.clone(),
+            router: application.name.clone(),
+            service: application.name.clone(),
+            hosts: application.hosts(&routing_config.domain),
+            paths: application.paths(),
+            strip_prefix: application.strip_prefix(),
+            tls: application
+                .configuration
+                .as_ref()
+                .and_then(|configuration| configuration.tls.clone()),
+            redirect: routing_config.tls.redirect,
+        }
+    }
+
+    /// Route of an application known by name only, enough to name its routing resources
+    pub fn named(application: &str) -> Self {
+        Self {
+            application: application.to_string(),
+            router: application.to_string(),
+            service: application.to_string(),
+            hosts: vec![],
+            paths: vec!["/".to_string()],
+            strip_prefix: false,
+            tls: None,
+            redirect: false,
+        }
+    }
+
+    /// Traefik rule matching any of the application hosts, and any of its path prefixes
+    fn rule(&self) -> String {
+        let hosts = self
+            .hosts
+            .iter()
+            .map(|host| format!("Host(`{}`)", host))
+            .collect::<Vec<_>>()
+            .join(" || ");
+        if !self.prefixed() {
+            return hosts;
+        }
+        let paths = self
+            .paths
+            .iter()
+            .map(|path| format!("PathPrefix(`{}`)", path))
+            .collect::<Vec<_>>()
+            .join(" || ");
+        format!("({}) && ({})", hosts, paths)
+    }
+
+    /// Whether the application only receives some paths of its hosts
+    fn prefixed(&self) -> bool {
+        self.paths.iter().all(|path| path != "/")
+    }
+
+    /// Traefik router matching the application hosts on https
+    pub fn secure_router(&self) -> String {
+        format!("{}--secure", self.router)
+    }
+
+    pub fn redirect_middleware(&self) -> String {
+        format!("{}--redirect", self.router)
+    }
+
+    pub fn redirected(&self) -> bool {
+        self.tls.is_some() && self.redirect
+    }
+
+    pub fn strip_middleware(&self) -> String {
+        format!("{}--strip", self.router)
+    }
+
+    pub fn stripped(&self) -> bool {
+        self.strip_prefix && self.prefixed()
+    }
+
+    /// Middlewares of the http router, the https redirection happens before any other
+    fn middlewares(&self) -> Vec<String> {
+        let mut middlewares = self.secure_middlewares();
+        if self.redirected() {
+            middlewares.insert(0, self.redirect_middleware());
+        }
+        middlewares
+    }
+
+    fn secure_middlewares(&self) -> Vec<String> {
+        if self.stripped() {
+            vec![self.strip_middleware()]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Labels read by the Traefik docker provider on each application container
+    pub fn docker_labels(&self, port: u16) -> HashMap<String, String> {
+        let mut labels = HashMap::from([
+            ("traefik.enable".to_string(), "true".to_string()),
+            (
+                format!("traefik.http.routers.{}.rule", self.router),
+                self.rule(),
+            ),
+            (
+                format!("traefik.http.routers.{}.entrypoints", self.router),
+                "web".to_string(),
+            ),
+            (
+                format!("traefik.http.routers.{}.service", self.router),
+                self.service.clone(),
+            ),
+            (
+                format!(
+                    "traefik.http.services.{}.loadbalancer.server.port",
+                    self.service
+                ),
+                port.to_string(),
+            ),
+        ]);
+        if self.stripped() {
+            labels.insert(
+                format!(
+                    "traefik.http.middlewares.{}.stripprefix.prefixes",
+                    self.strip_middleware()
+                ),
+                self.paths.join(","),
+            );
+        }
+        let middlewares = self.middlewares();
+        if !middlewares.is_empty() {
+            labels.insert(
+                format!("traefik.http.routers.{}.middlewares", self.router),
+                middlewares.join(","),
+            );
+        }
+        let Some(ref tls) = self.tls else {
+            return labels;
+        };
+        let secure_router = self.secure_router();
+        labels.extend([
+            (
+                format!("traefik.http.routers.{}.rule", secure_router),
+                self.rule(),
+            ),
+            (
+                format!("traefik.http.routers.{}.entrypoints", secure_router),
+                "websecure".to_string(),
+            ),
+            (
+                format!("traefik.http.routers.{}.service", secure_router),
+                self.service.clone(),
+            ),
+            (
+                format!("traefik.http.routers.{}.tls", secure_router),
+                "true".to_string(),
+            ),
+        ]);
+        if let Tls::Acme = tls {
+            labels.insert(
+                format!("traefik.http.routers.{}.tls.certresolver", secure_router),
+                ACME_RESOLVER.to_string(),
+            );
+        }
+        let secure_middlewares = self.secure_middlewares();
+        if !secure_middlewares.is_empty() {
+            labels.insert(
+                format!("traefik.http.routers.{}.middlewares", secure_router),
+                secure_middlewares.join(","),
+            );
+        }
+        if self.redirected() {
+            let middleware = self.redirect_middleware();
+            labels.extend([
+                (
+                    format!(
+                        "traefik.http.middlewares.{}.redirectscheme.scheme",
+                        middleware
+                    ),
+                    "https".to_string(),
+                ),
+                (
+                    format!(
+                        "traefik.http.middlewares.{}.redirectscheme.permanent",
+                        middleware
+                    ),
+                    "true".to_string(),
+                ),
+            ]);
+        }
+        labels
+    }
+
+    /// Traefik file provider configuration adding the certificate to the default store,
+    /// it is picked for the requests matching its names
+    pub fn traefik_certificate_config(&self, certificate: &Certificate) -> String {
+        format!(
+            "tls:\n  certificates:\n    - certFile: |\n{}\n      keyFile: |\n{}\n",
+            indent(&certificate.certificate, 10),
+            indent(&certificate.key, 10)
+        )
+    }
+
+    /// Kubernetes service of the application pods, Traefik builds its own service from it
+    pub fn kubernetes_service(&self, port: Option<u16>) -> Result<Service, Error> {
+        Ok(serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": {
+                "name": self.service.clone(),
+                "labels": {
+                    "cleverclown.app": self.application.clone(),
+                },
+            },
+            "spec": {
+                "ports": [
+                    {
+                        "name": "app",
+                        "port": port,
+                        "targetPort": port,
+                    }
+                ],
+                "selector": {
+                    "cleverclown.app": self.application.clone(),
+                    "cleverclown.process": WEB_PROCESS,
+                },
+            },
+        }))?)
+    }
+
+    /// Kubernetes ingresses served by Traefik, the https one only when the application has tls
+    pub fn kubernetes_ingresses(&self, namespace: &str) -> Result<Vec<Ingress>, Error> {
+        let mut annotations = HashMap::from([(
+            "traefik.ingress.kubernetes.io/router.entrypoints".to_string(),
+            "web".to_string(),
+        )]);
+        let middlewares = self.middlewares();
+        if !middlewares.is_empty() {
+            annotations.insert(
+                "traefik.ingress.kubernetes.io/router.middlewares".to_string(),
+                kubernetes_middlewares(namespace, &middlewares),
+            );
+        }
+        let mut ingresses = vec![self.kubernetes_ingress(&self.router, annotations, None)?];
+        if let Some(ref tls) = self.tls {
+            let mut annotations = HashMap::from([
+                (
+                    "traefik.ingress.kubernetes.io/router.entrypoints".to_string(),
+                    "websecure".to_string(),
+                ),
+                (
+                    "traefik.ingress.kubernetes.io/router.tls".to_string(),
+                    "true".to_string(),
+                ),
+            ]);
+            if let Tls::Acme = tls {
+                annotations.insert(
+                    "traefik.ingress.kubernetes.io/router.tls.certresolver".to_string(),
+                    ACME_RESOLVER.to_string(),
+                );
+            }
+            let secure_middlewares = self.secure_middlewares();
+            if !secure_middlewares.is_empty() {
+                annotations.insert(
+                    "traefik.ingress.kubernetes.io/router.middlewares".to_string(),
+                    kubernetes_middlewares(namespace, &secure_middlewares),
+                );
+            }
+            let tls_secret = match tls {
+                Tls::Certificate { .. } => Some(self.kubernetes_tls_secret_name()),
+                _ => None,
+            };
+            ingresses.push(self.kubernetes_ingress(
+                &self.secure_router(),
+                annotations,
+                tls_secret,
+            )?);
+        }
+        Ok(ingresses)
+    }
+
+    fn kubernetes_ingress(
+        &self,
+        name: &str,
+        annotations: HashMap<String, String>,
+        tls_secret: Option<String>,
+    ) -> Result<Ingress, Error> {
+        Ok(serde_json::from_value(json!({
+            "apiVersion": "networking.k8s.io/v1",
+            "kind": "Ingress",
+            "metadata": {
+                "name": name,
+                "labels": {
+                    "cleverclown.app": self.application.clone(),
+                },
+                "annotations": annotations,
+            },
+            "spec": {
+                "tls": tls_secret.map(|secret_name| vec![json!({
+                    "hosts": self.hosts.clone(),
+                    "secretName": secret_name,
+                })]),
+                "rules": self
+                    .hosts
+                    .iter()
+                    .map(|host| json!({
+                        "host": host,
+                        "http": {
+                            "paths": self
+                                .paths
+                                .iter()
+                                .map(|path| json!({
+                                    "path": path,
+                                    "pathType": "Prefix",
+                                    "backend": {
+                                        "service": {
+                                            "name": self.service.clone(),
+                                            "port": {
+                                                "name": "app",
+                                            },
+                                        },
+                                    },
+                                }))
+                                .collect::<Vec<_>>(),
+                        },
+                    }))
+                    .collect::<Vec<_>>(),
+            },
+        }))?)
+    }
+
+    /// Traefik middleware redirecting http requests of the application to https
+    pub fn kubernetes_redirect_middleware(&self) -> DynamicObject {
+        let mut middleware =
+            DynamicObject::new(&self.redirect_middleware(), &traefik_middleware_resource()).data(
+                json!({
+                    "spec": {
+                        "redirectScheme": {
+                            "scheme": "https",
+                            "permanent": true,
+                        },
+                    },
+                }),
+            );
+        middleware.metadata.labels = Some(BTreeMap::from([(
+            "cleverclown.app".to_string(),
+            self.application.clone(),
+        )]));
+        middleware
+    }
+
+    /// Traefik middleware removing the path prefixes of the application from the requests
+    pub fn kubernetes_strip_middleware(&self) -> DynamicObject {
+        let mut middleware =
+            DynamicObject::new(&self.strip_middleware(), &traefik_middleware_resource()).data(
+                json!({
+                    "spec": {
+                        "stripPrefix": {
+                            "prefixes": self.paths.clone(),
+                        },
+                    },
+                }),
+            );
+        middleware.metadata.labels = Some(BTreeMap::from([(
+            "cleverclown.app".to_string(),
+            self.application.clone(),
+        )]));
+        middleware
+    }
+
+    pub fn kubernetes_tls_secret_name(&self) -> String {
+        format!("{}--tls", self.router)
+    }
+
+    pub fn kubernetes_tls_secret(&self, certificate: &Certificate) -> Result<Secret, Error> {
+        Ok(serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {
+                "name": self.kubernetes_tls_secret_name(),
+                "labels": {
+                    "cleverclown.app": self.application.clone(),
+                },
+            },
+            "type": "kubernetes.io/tls",
+            "stringData": {
+                "tls.crt": certificate.certificate.clone(),
+                "tls.key": certificate.key.clone(),
+            },
+        }))?)
+    }
+}
+
+/// Middleware custom resource of the Traefik kubernetes crd provider
+pub fn traefik_middleware_resource() -> ApiResource {
+    ApiResource::from_gvk_with_plural(
+        &GroupVersionKind::gvk("traefik.io", "v1alpha1", "Middleware"),
+        "middlewares",
+    )
+}
+
+/// Middleware references of the Traefik kubernetes crd provider, prefixed by their namespace
+fn kubernetes_middlewares(namespace: &str, middlewares: &[String]) -> String {
+    middlewares
+        .iter()
+        .map(|middleware| format!("{}-{}@kubernetescrd", namespace, middleware))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn indent(text: &str, width: usize) -> String {
+    text.trim_end()
+        .lines()
+        .map(|line| format!("{}{}", " ".repeat(width), line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,28 +1,80 @@
-use std::sync::Arc;
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use axum::{
-    extract::{Path, State},
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
     http::StatusCode,
-    response::IntoResponse,
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse,
+    },
     routing::{delete, get, post},
     Json, Router,
 };
-use log::error;
+use futures::{stream, SinkExt, Stream, StreamExt};
+use log::{error, warn};
+use serde::Deserialize;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::broadcast::{error::RecvError, Receiver},
+};
 
-use crate::domain::{model::Application, reconcile, Event, ReconciliationService};
+use crate::domain::{
+    deployment::LogReporter,
+    model::{
+        AddonAttachment, AddonKind, Application, ExecOptions, GitCredential, LogOptions,
+        RegistryCredential, RunOutput, Secret, TerminalSize,
+    },
+    port::ExecSession,
+    reconcile, Event, ReconciliationService,
+};
 
-pub fn router(reconciliation: ReconciliationService) -> Router {
+pub fn router(reconciliation: Arc<ReconciliationService>) -> Router {
     Router::new()
         .route("/", get(list_applications))
         .route("/", post(deploy_application))
+        .route("/:app_name", get(get_application))
         .route("/:app_name", delete(destroy_application))
-        .with_state(Arc::new(reconciliation))
+        .route("/:app_name/logs", get(stream_application_logs))
+        .route("/:app_name/run", post(run_command))
+        .route("/:app_name/instances/:instance_id/exec", get(exec_instance))
+        .route("/:app_name/releases", get(list_releases))
+        .route("/:app_name/rollback", post(rollback_application))
+        .route("/:app_name/addons", post(attach_addon))
+        .route("/:app_name/addons/:addon_name", delete(detach_addon))
+        .route("/secrets", get(list_secrets))
+        .route("/secrets", post(save_secret))
+        .route("/secrets/:secret_name", delete(delete_secret))
+        .route("/git-credentials", get(list_git_credentials))
+        .route("/git-credentials", post(save_git_credential))
+        .route(
+            "/git-credentials/:credential_name",
+            delete(delete_git_credential),
+        )
+        .route("/registry-credentials", get(list_registry_credentials))
+        .route("/registry-credentials", post(save_registry_credential))
+        .route(
+            "/registry-credentials/:registry",
+            delete(delete_registry_credential),
+        )
+        .route("/addons", get(list_addons))
+        .route("/addons", post(create_addon))
+        .route("/addons/:addon_name", delete(delete_addon))
+        .route("/deployments/:deployment_id", get(get_deployment))
+        .route(
+            "/deployments/:deployment_id/logs",
+            get(stream_deployment_logs),
+        )
+        .with_state(reconciliation)
 }
 
 async fn list_applications(State(service): State<Arc<ReconciliationService>>) -> impl IntoResponse {
     crate::domain::list_applications(&service)
         .await
-        .map(|applications| Json(applications))
+        .map(Json)
         .map_err(|e| {
             error!("Error during list_application {:?}", e);
             (
@@ -32,13 +84,33 @@ async fn list_applications(State(service): State<Arc<ReconciliationService>>) ->
         })
 }
 
+async fn get_application(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(app_name): Path<String>,
+) -> impl IntoResponse {
+    match crate::domain::get_application(&service, app_name.clone()).await {
+        Ok(Some(application)) => Ok(Json(application)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Application {app_name} not found"),
+        )),
+        Err(e) => {
+            error!("Error during get_application {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            ))
+        }
+    }
+}
+
 async fn deploy_application(
     State(service): State<Arc<ReconciliationService>>,
     Json(payload): Json<Application>,
 ) -> impl IntoResponse {
-    reconcile(Event::Deploy(payload), service.as_ref())
+    crate::domain::deploy(service, payload)
         .await
-        .map(|_| (StatusCode::OK, "Application deployed"))
+        .map(|deployment| (StatusCode::ACCEPTED, Json(deployment)))
         .map_err(|e| {
             error!("Error during deploy_application {:?}", e);
             (
@@ -48,11 +120,22 @@ async fn deploy_application(
         })
 }
 
+#[derive(Deserialize)]
+struct DestroyRequest {
+    #[serde(default)]
+    purge_volumes: bool,
+}
+
 async fn destroy_application(
     State(service): State<Arc<ReconciliationService>>,
     Path(app_name): Path<String>,
+    Query(request): Query<DestroyRequest>,
 ) -> impl IntoResponse {
-    reconcile(Event::Destroy(app_name), service.as_ref())
+    let event = Event::Destroy {
+        application: app_name,
+        purge_volumes: request.purge_volumes,
+    };
+    reconcile(event, service.as_ref(), &LogReporter)
         .await
         .map(|_| (StatusCode::OK, "Application destoyed"))
         .map_err(|e| {
@@ -63,3 +146,497 @@ async fn destroy_application(
             )
         })
 }
+
+async fn stream_application_logs(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(app_name): Path<String>,
+    Query(options): Query<LogOptions>,
+) -> impl IntoResponse {
+    match crate::domain::application_logs(&service, app_name.clone(), options).await {
+        Ok(Some(lines)) => {
+            let body = lines.map(|line| {
+                Ok::<String, Infallible>(match line {
+                    Ok(line) => format!("[{}] {}\n", line.instance, line.message),
+                    Err(e) => {
+                        error!("Error during stream_application_logs {:?}", e);
+                        format!("Something went wrong: {e}\n")
+                    }
+                })
+            });
+            Ok(Body::from_stream(body))
+        }
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("No instance of application {app_name} found"),
+        )),
+        Err(e) => {
+            error!("Error during stream_application_logs {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RunRequest {
+    command: Vec<String>,
+    /// In seconds, default to 600
+    timeout: Option<u64>,
+}
+
+async fn run_command(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(app_name): Path<String>,
+    Json(payload): Json<RunRequest>,
+) -> impl IntoResponse {
+    let timeout = payload.timeout.unwrap_or(600);
+    match crate::domain::run_command(
+        &service,
+        app_name.clone(),
+        payload.command,
+        Duration::from_secs(timeout),
+    )
+    .await
+    {
+        Ok(Some(output)) => {
+            let body = output.map(move |output| {
+                Ok::<String, Infallible>(match output {
+                    Ok(RunOutput::Line(line)) => format!("{}\n", line),
+                    Ok(RunOutput::Exited(code)) => format!("Exited with code {}\n", code),
+                    Ok(RunOutput::TimedOut) => format!("Timed out after {}s\n", timeout),
+                    Err(e) => {
+                        error!("Error during run_command {:?}", e);
+                        format!("Something went wrong: {e}\n")
+                    }
+                })
+            });
+            Ok(Body::from_stream(body))
+        }
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Application {app_name} is not deployed"),
+        )),
+        Err(e) => {
+            error!("Error during run_command {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            ))
+        }
+    }
+}
+
+/// Control messages of an exec session, sent as text frames
+#[derive(Deserialize)]
+enum ExecControl {
+    Resize(TerminalSize),
+}
+
+async fn exec_instance(
+    State(service): State<Arc<ReconciliationService>>,
+    Path((app_name, instance_id)): Path<(String, String)>,
+    Query(options): Query<ExecOptions>,
+    upgrade: WebSocketUpgrade,
+) -> impl IntoResponse {
+    match crate::domain::exec(&service, app_name.clone(), instance_id.clone(), &options).await {
+        Ok(Some(session)) => Ok(upgrade.on_upgrade(|socket| exec_session(socket, session))),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("No running instance {instance_id} of application {app_name} found"),
+        )),
+        Err(e) => {
+            error!("Error during exec_instance {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            ))
+        }
+    }
+}
+
+/// Relay the socket to the command: binary frames are written to its stdin and its output is sent back
+/// as binary frames, a last text frame gives its exit code
+async fn exec_session(socket: WebSocket, session: ExecSession) {
+    let ExecSession {
+        mut output,
+        input,
+        resize,
+        exit_code,
+    } = session;
+    let (mut sender, mut receiver) = socket.split();
+    let input_relay = tokio::spawn(async move {
+        let mut input = input;
+        while let Some(Ok(message)) = receiver.next().await {
+            match message {
+                Message::Binary(data) => {
+                    if let Some(ref mut input) = input {
+                        if input.write_all(&data).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Message::Text(text) => match serde_json::from_str::<ExecControl>(&text) {
+                    Ok(ExecControl::Resize(size)) => {
+                        if let Some(ref resize) = resize {
+                            let _ = resize.send(size).await;
+                        }
+                    }
+                    Err(e) => warn!("Unknown exec control message {}: {:?}", text, e),
+                },
+                Message::Close(_) => return,
+                _ => {}
+            }
+        }
+    });
+    while let Some(data) = output.next().await {
+        let message = match data {
+            Ok(data) => Message::Binary(data),
+            Err(e) => {
+                error!("Error during exec_session {:?}", e);
+                Message::Text(format!("Something went wrong: {e}"))
+            }
+        };
+        if sender.send(message).await.is_err() {
+            break;
+        }
+    }
+    // Dropping stdin ends commands still reading it
+    input_relay.abort();
+    let status = match exit_code.await {
+        Ok(Some(code)) => format!("Exited with code {}", code),
+        Ok(None) => "Exited".to_string(),
+        Err(e) => {
+            error!("Error during exec_session {:?}", e);
+            format!("Something went wrong: {e}")
+        }
+    };
+    let _ = sender.send(Message::Text(status)).await;
+    let _ = sender.send(Message::Close(None)).await;
+}
+
+async fn list_releases(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(app_name): Path<String>,
+) -> impl IntoResponse {
+    crate::domain::list_releases(&service, app_name)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Error during list_releases {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+#[derive(Deserialize)]
+struct RollbackRequest {
+    release: Option<u32>,
+}
+
+async fn rollback_application(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(app_name): Path<String>,
+    payload: Option<Json<RollbackRequest>>,
+) -> impl IntoResponse {
+    let release = payload.and_then(|Json(payload)| payload.release);
+    crate::domain::rollback(service, app_name, release)
+        .await
+        .map(|deployment| (StatusCode::ACCEPTED, Json(deployment)))
+        .map_err(|e| {
+            error!("Error during rollback_application {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn get_deployment(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(deployment_id): Path<String>,
+) -> impl IntoResponse {
+    match crate::domain::get_deployment(&service, deployment_id.clone()).await {
+        Ok(Some(deployment)) => Ok(Json(deployment)),
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Deployment {deployment_id} not found"),
+        )),
+        Err(e) => {
+            error!("Error during get_deployment {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            ))
+        }
+    }
+}
+
+async fn stream_deployment_logs(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(deployment_id): Path<String>,
+) -> impl IntoResponse {
+    match crate::domain::deployment_logs(&service, deployment_id.clone()).await {
+        Ok(Some(logs)) => {
+            let history = stream::iter(logs.history);
+            let live = match logs.live {
+                Some(receiver) => live_lines(receiver).left_stream(),
+                None => stream::empty().right_stream(),
+            };
+            let events = history
+                .chain(live)
+                .map(|line| Ok::<SseEvent, Infallible>(SseEvent::default().data(line)));
+            Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+        }
+        Ok(None) => Err((
+            StatusCode::NOT_FOUND,
+            format!("Deployment {deployment_id} not found"),
+        )),
+        Err(e) => {
+            error!("Error during stream_deployment_logs {:?}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            ))
+        }
+    }
+}
+
+/// Lines received until the deployment ends, a slow client is told about skipped lines
+fn live_lines(receiver: Receiver<String>) -> impl Stream<Item = String> {
+    stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(line) => Some((line, receiver)),
+            Err(RecvError::Lagged(skipped)) => {
+                Some((format!("... {} lines skipped", skipped), receiver))
+            }
+            Err(RecvError::Closed) => None,
+        }
+    })
+}
+
+async fn list_secrets(State(service): State<Arc<ReconciliationService>>) -> impl IntoResponse {
+    crate::domain::list_secrets(&service)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Error during list_secrets {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn save_secret(
+    State(service): State<Arc<ReconciliationService>>,
+    Json(payload): Json<Secret>,
+) -> impl IntoResponse {
+    crate::domain::save_secret(&service, payload)
+        .await
+        .map(|_| (StatusCode::OK, "Secret saved"))
+        .map_err(|e| {
+            error!("Error during save_secret {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn delete_secret(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(secret_name): Path<String>,
+) -> impl IntoResponse {
+    crate::domain::delete_secret(&service, secret_name)
+        .await
+        .map(|_| (StatusCode::OK, "Secret deleted"))
+        .map_err(|e| {
+            error!("Error during delete_secret {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn list_git_credentials(
+    State(service): State<Arc<ReconciliationService>>,
+) -> impl IntoResponse {
+    crate::domain::list_git_credentials(&service)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Error during list_git_credentials {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn save_git_credential(
+    State(service): State<Arc<ReconciliationService>>,
+    Json(payload): Json<GitCredential>,
+) -> impl IntoResponse {
+    crate::domain::save_git_credential(&service, payload)
+        .await
+        .map(|_| (StatusCode::OK, "Git credential saved"))
+        .map_err(|e| {
+            error!("Error during save_git_credential {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn delete_git_credential(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(credential_name): Path<String>,
+) -> impl IntoResponse {
+    crate::domain::delete_git_credential(&service, credential_name)
+        .await
+        .map(|_| (StatusCode::OK, "Git credential deleted"))
+        .map_err(|e| {
+            error!("Error during delete_git_credential {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn list_registry_credentials(
+    State(service): State<Arc<ReconciliationService>>,
+) -> impl IntoResponse {
+    crate::domain::list_registry_credentials(&service)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Error during list_registry_credentials {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn save_registry_credential(
+    State(service): State<Arc<ReconciliationService>>,
+    Json(payload): Json<RegistryCredential>,
+) -> impl IntoResponse {
+    crate::domain::save_registry_credential(&service, payload)
+        .await
+        .map(|_| (StatusCode::OK, "Registry credential saved"))
+        .map_err(|e| {
+            error!("Error during save_registry_credential {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn delete_registry_credential(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(registry): Path<String>,
+) -> impl IntoResponse {
+    crate::domain::delete_registry_credential(&service, registry)
+        .await
+        .map(|_| (StatusCode::OK, "Registry credential deleted"))
+        .map_err(|e| {
+            error!("Error during delete_registry_credential {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+#[derive(Deserialize)]
+struct AddonRequest {
+    name: String,
+    kind: AddonKind,
+}
+
+async fn list_addons(State(service): State<Arc<ReconciliationService>>) -> impl IntoResponse {
+    crate::domain::list_addons(&service)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            error!("Error during list_addons {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn create_addon(
+    State(service): State<Arc<ReconciliationService>>,
+    Json(payload): Json<AddonRequest>,
+) -> impl IntoResponse {
+    crate::domain::create_addon(&service, payload.name, payload.kind)
+        .await
+        .map(|_| (StatusCode::OK, "Add-on created"))
+        .map_err(|e| {
+            error!("Error during create_addon {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn delete_addon(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(addon_name): Path<String>,
+) -> impl IntoResponse {
+    crate::domain::delete_addon(&service, addon_name)
+        .await
+        .map(|_| (StatusCode::OK, "Add-on deleted"))
+        .map_err(|e| {
+            error!("Error during delete_addon {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn attach_addon(
+    State(service): State<Arc<ReconciliationService>>,
+    Path(app_name): Path<String>,
+    Json(payload): Json<AddonAttachment>,
+) -> impl IntoResponse {
+    crate::domain::attach_addon(&service, app_name, payload)
+        .await
+        .map(|_| (StatusCode::OK, "Add-on attached"))
+        .map_err(|e| {
+            error!("Error during attach_addon {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
+
+async fn detach_addon(
+    State(service): State<Arc<ReconciliationService>>,
+    Path((app_name, addon_name)): Path<(String, String)>,
+) -> impl IntoResponse {
+    crate::domain::detach_addon(&service, app_name, addon_name)
+        .await
+        .map(|_| (StatusCode::OK, "Add-on detached"))
+        .map_err(|e| {
+            error!("Error during detach_addon {:?}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Something went wrong: {e}"),
+            )
+        })
+}
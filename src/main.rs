@@ -1,11 +1,12 @@
-use std::{error::Error, str::FromStr};
+use std::{error::Error, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use bollard::{Docker, API_DEFAULT_VERSION};
 use config::{load_config, Orchestrator};
 use domain::port::ContainerExecutor;
 use infra::{
-    docker::DockerContainerExecutor, kubernetes::KubernetesContainerExecutor, web::router,
+    docker::DockerContainerExecutor, file::FileRepository,
+    kubernetes::KubernetesContainerExecutor, web::router,
 };
 use kube::Client;
 use log::{info, warn, LevelFilter};
@@ -39,23 +40,63 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let service: Box<dyn ContainerExecutor + 'static + Sync + Send> = match &config.orchestrator {
         Orchestrator::Docker(ref docker_config) => Box::new(DockerContainerExecutor {
             docker_config: docker_config.clone(),
-            routing_config: config.routing,
+            routing_config: config.routing.clone(),
             docker: Docker::connect_with_socket(&docker_config.socket, 120, API_DEFAULT_VERSION)
                 .context("Can't connect to docker socket")?,
+            request_samples: Default::default(),
         }),
         Orchestrator::Kubernetes(ref kube_config) => Box::new(KubernetesContainerExecutor {
             kube_config: kube_config.clone(),
-            routing_config: config.routing,
+            routing_config: config.routing.clone(),
             client: Client::try_default().await?,
         }),
     };
+    let repository = FileRepository {
+        directory: config.storage.directory.clone().into(),
+    };
     let service = domain::ReconciliationService {
+        application_repository: Box::new(repository.clone()),
+        deployment_repository: Box::new(repository.clone()),
+        secret_repository: Box::new(repository.clone()),
+        release_repository: Box::new(repository.clone()),
+        git_credential_repository: Box::new(repository.clone()),
+        registry_credential_repository: Box::new(repository.clone()),
+        addon_repository: Box::new(repository),
         container_executor: service,
+        routing_config: config.routing.clone(),
+        resources_config: config.resources.clone(),
+        release_timeout: Duration::from_secs(config.reconciliation.release_timeout),
+        application_locks: Default::default(),
+        live_logs: Default::default(),
+        route_claims: Default::default(),
+        autoscaled_replicas: Default::default(),
     };
 
     service.container_executor.ensure_routing().await?;
     // Possible feature: gracefully stop routing on shutdown hook with config
 
+    let service = Arc::new(service);
+    if config.reconciliation.interval > 0 {
+        info!(
+            "Start background reconciliation every {}s",
+            config.reconciliation.interval
+        );
+        tokio::spawn(domain::reconciliation_loop(
+            service.clone(),
+            Duration::from_secs(config.reconciliation.interval),
+        ));
+    }
+    if config.reconciliation.autoscaling_interval > 0 {
+        info!(
+            "Start autoscaling every {}s",
+            config.reconciliation.autoscaling_interval
+        );
+        tokio::spawn(domain::autoscaling_loop(
+            service.clone(),
+            Duration::from_secs(config.reconciliation.autoscaling_interval),
+        ));
+    }
+
     info!("Start cleverclown http server on {}", http_bind);
     let listener = TcpListener::bind(http_bind).await.unwrap();
     axum::serve(listener, router(service)).await?;